@@ -0,0 +1,68 @@
+use crate::dsl::TimeFrame;
+use crate::simulator::SimulationResult;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Completed results of a sweep, tied to the DSL source they came from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub dsl_hash: String,
+    pub results: Vec<SimulationResult>,
+}
+
+impl Checkpoint {
+    /// Loads completed results for this hash. A missing or unreadable file, or one
+    /// written for different DSL content or options, starts an empty checkpoint.
+    pub fn load(path: &Path, dsl_hash: &str) -> Self {
+        let empty = Checkpoint {
+            dsl_hash: dsl_hash.to_string(),
+            results: Vec::new(),
+        };
+
+        let Ok(content) = fs::read_to_string(path) else {
+            return empty;
+        };
+        match serde_json::from_str::<Checkpoint>(&content) {
+            Ok(checkpoint) if checkpoint.dsl_hash == dsl_hash => checkpoint,
+            Ok(_) => {
+                eprintln!("Checkpoint {:?} was written for a different DSL file or options; starting over", path);
+                empty
+            }
+            Err(e) => {
+                eprintln!("Ignoring unreadable checkpoint {:?}: {}", path, e);
+                empty
+            }
+        }
+    }
+
+    /// Writes via a temporary file so an interrupted save never corrupts the checkpoint
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut temp_path = PathBuf::from(path);
+        temp_path.as_mut_os_string().push(".tmp");
+        fs::write(&temp_path, serde_json::to_string(self)?)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Previously completed result for this (pattern, amount, frame) combination
+    pub fn find(&self, pattern_name: &str, initial_amount: Decimal, time_frame: &TimeFrame) -> Option<&SimulationResult> {
+        self.results.iter().find(|r| {
+            r.pattern_name == pattern_name
+                && r.initial_amount == initial_amount
+                && r.time_frame.to_string() == time_frame.to_string()
+        })
+    }
+}
+
+/// Stable FNV-1a hash, as hex, for identifying content across runs
+pub fn content_hash(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
@@ -1,8 +1,9 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct StockDSL {
@@ -10,27 +11,451 @@ pub struct StockDSL {
     pub time_frames: Vec<TimeFrame>,
     pub investments: HashMap<String, Investment>,
     pub patterns: HashMap<String, Vec<String>>,
+    pub descriptions: HashMap<String, String>, // Pattern name to its DESCRIBE text
+    pub pattern_weights: HashMap<String, Vec<Decimal>>, // Per-entry weights of weighted patterns
     pub tests: Vec<String>,
+    pub rolling_window: Option<u32>, // Rolling return window in weeks
+    pub entry_price: PriceField,
+    pub prices_file: Option<PathBuf>, // CSV of ticker,price used instead of fetching
+    pub ticker_suffixes: Vec<String>, // Exchange suffixes tried when a ticker has no data
+    pub max_trades: Option<usize>, // Abort a simulation that records more trades than this
+    pub benchmark: Vec<(String, Decimal)>, // (ticker, weight) held at fixed weights
+    pub vol_target: Option<Decimal>, // Target annualized volatility as a fraction
+    pub name_match: NameMatch,
+    pub ewma_lambda: Option<Decimal>, // Decay for recency-weighted return and volatility estimates
+    pub initial_deploy: InitialDeploy,
+    pub price_check: PriceCheck,
+    pub end_align: EndAlign,
+    pub withdrawal: Option<Withdrawal>, // Periodic drawdown of the portfolio
+    pub return_endpoint: ReturnEndpoint,
+    pub fee: Option<Fee>, // Transaction cost charged on every trade
+    pub fee_models: Vec<(String, Fee)>, // Named alternatives compared by --compare-fees, in file order
+    pub cash_buffer: Option<Decimal>, // Fraction of the portfolio always held as cash
+    pub currency: String, // ISO code of the invest amounts, for display
+    pub value_at_risk: Option<VarSpec>, // One-week VaR to report from trailing weekly returns
+    pub warnings: Vec<String>, // Suspicious but valid input, for the caller to report
+    pub ignored_lines: Vec<String>, // Unknown or incomplete directives, also listed in warnings
+    pub included_files: Vec<PathBuf>, // Every INCLUDEd file, in the order read
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeFrame {
     pub duration: u32,
     pub unit: TimeUnit,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TimeUnit {
     Days,
     Weeks,
+    Months,
     Years,
 }
 
+impl fmt::Display for TimeFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.unit {
+            TimeUnit::Days => "d",
+            TimeUnit::Weeks => "w",
+            TimeUnit::Months => "m",
+            TimeUnit::Years => "y",
+        };
+        write!(f, "{}{}", self.duration, unit)
+    }
+}
+
+impl TimeFrame {
+    /// Calendar length in days, a month counting as 30 and a year as 365;
+    /// wide enough that no duration overflows
+    pub fn approx_days(&self) -> u64 {
+        let duration = u64::from(self.duration);
+        match self.unit {
+            TimeUnit::Days => duration,
+            TimeUnit::Weeks => duration * 7,
+            TimeUnit::Months => duration * 30,
+            TimeUnit::Years => duration * 365,
+        }
+    }
+
+    pub fn total_weeks(&self) -> u32 {
+        match self.unit {
+            TimeUnit::Days => self.duration.div_ceil(7), // Round up to nearest week
+            TimeUnit::Weeks => self.duration,
+            TimeUnit::Months => (self.duration * 52).div_ceil(12),
+            TimeUnit::Years => self.duration * 52,
+        }
+    }
+}
+
+/// Which price of a bar is used to fill trades
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PriceField {
+    Open,
+    High,
+    Low,
+    #[default]
+    Close,
+}
+
+/// Whether a trailing partial week counts in weekly return series
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EndAlign {
+    #[default]
+    Partial,  // Use the latest close even if its week hasn't finished
+    Complete, // Stop at the last week that reached Friday
+}
+
+/// How VAR estimates the loss quantile of weekly returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum VarMethod {
+    #[default]
+    Historical, // The empirical quantile of past weekly returns
+    Parametric, // From their mean and standard deviation, assuming a normal distribution
+}
+
+/// Value-at-risk estimate requested with VAR
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VarSpec {
+    pub confidence: Decimal, // Fraction, e.g. 0.95
+    pub method: VarMethod,
+}
+
+/// Commission charged on each trade
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fee {
+    Flat(Decimal),       // Fixed amount per trade
+    Percentage(Decimal), // Fraction of the traded amount
+}
+
+impl Fee {
+    /// Cost of a trade of `amount`, never more than the amount itself
+    pub fn on(&self, amount: Decimal) -> Decimal {
+        let fee = match self {
+            Fee::Flat(fee) => *fee,
+            Fee::Percentage(fraction) => amount * fraction,
+        };
+        fee.min(amount)
+    }
+}
+
+/// Which prices anchor the start and end of the trailing annual return
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ReturnEndpoint {
+    #[default]
+    Raw,            // The earliest and latest closes
+    Trimmed(usize), // The average of this many closes at each end
+}
+
+/// Closes averaged at each end by `RETURN_ENDPOINT trimmed` without a count
+const DEFAULT_TRIMMED_BARS: usize = 5;
+
+/// Sanity check of a fetched live price against the latest historical close
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceCheck {
+    pub max_deviation: Decimal, // Largest relative difference accepted silently
+    pub strict: bool,           // Fail the fetch instead of warning
+}
+
+impl Default for PriceCheck {
+    fn default() -> Self {
+        PriceCheck { max_deviation: Decimal::new(2, 1), strict: false }
+    }
+}
+
+/// A fixed amount taken out of the portfolio on a regular schedule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Withdrawal {
+    pub amount: Decimal,
+    pub per_year: u32, // Withdrawals per 52-week year: 52, 12, 4 or 1
+}
+
+impl Withdrawal {
+    /// Whether a withdrawal falls in `week`, spreading them evenly over each 52-week year
+    pub fn due(&self, week: u32) -> bool {
+        week > 0 && week * self.per_year / 52 > (week - 1) * self.per_year / 52
+    }
+}
+
+/// How the initial capital enters the market
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum InitialDeploy {
+    #[default]
+    Immediate,  // All of it in week 1
+    Spread(u32), // Equal parts at the start of this many consecutive weeks
+}
+
+/// How pattern entries are matched to investment names
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NameMatch {
+    #[default]
+    Exact,
+    Fuzzy, // Ignore case and spacing, then accept a unique word prefix or near-miss spelling
+}
+
+/// Every directive the parser understands
+const KEYWORDS: &[&str] = &[
+    "SET", "INCLUDE", "INVEST", "TIME", "INVESTMENT", "PATTERN", "DESCRIBE", "TEST",
+    "ROLLING", "PRICES", "TICKER_SUFFIXES", "BENCHMARK", "VOL_TARGET", "EWMA_LAMBDA",
+    "INITIAL_DEPLOY", "PRICE_CHECK", "END_ALIGN", "WITHDRAW", "CASH_BUFFER", "FEE",
+    "FEE_MODEL", "RETURN_ENDPOINT", "MAX_TRADES", "ENTRY_PRICE", "NAME_MATCH", "DIVIDEND",
+    "CURRENCY", "VAR",
+];
+
+/// Currency amounts are shown in without a CURRENCY directive
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// Largest edit distance a fuzzy name match may be from the investment name
+const FUZZY_NAME_DISTANCE: usize = 2;
+
 #[derive(Debug, Clone)]
 pub struct Investment {
     pub ticker: String,
     pub name: String,
-    pub price: Option<Decimal>, // Will be fetched from Yahoo Finance
+    pub dividend_yield: Option<Decimal>, // Annual yield from DIVIDEND, added to the price return
+}
+
+/// Parses `10%` or `0.1` as the fraction 0.1
+fn parse_fraction(value: &str) -> Result<Decimal, Box<dyn std::error::Error>> {
+    match value.strip_suffix('%') {
+        Some(percent) => Ok(percent.trim().parse::<Decimal>()? / Decimal::from(100)),
+        None => Ok(value.parse::<Decimal>()?),
+    }
+}
+
+/// Parses a `directive` fee, flat (`4.95`) or a share of the trade (`0.1%`)
+fn parse_fee(directive: &str, value: &str) -> Result<Fee, Box<dyn std::error::Error>> {
+    let fee = if value.ends_with('%') {
+        Fee::Percentage(parse_fraction(value)?)
+    } else {
+        Fee::Flat(value.parse::<Decimal>()?)
+    };
+    if matches!(fee, Fee::Flat(amount) | Fee::Percentage(amount) if amount < Decimal::ZERO) {
+        return Err(format!("{} can't be negative: {}", directive, value).into());
+    }
+    Ok(fee)
+}
+
+/// Expands an inclusive range such as `1000..5000 step 1000` into its amounts
+fn parse_amount_range(range: &str) -> Result<Vec<Decimal>, Box<dyn std::error::Error>> {
+    let tokens: Vec<&str> = range.split_whitespace().collect();
+    let (bounds, step) = match tokens.as_slice() {
+        [bounds, keyword, step] if keyword.eq_ignore_ascii_case("step") => (*bounds, *step),
+        _ => return Err("expected '<start>..<end> step <step>'".into()),
+    };
+    let (start, end) = bounds.split_once("..").ok_or("expected '<start>..<end>'")?;
+    let start = start.parse::<Decimal>()?;
+    let end = end.parse::<Decimal>()?;
+    let step = step.parse::<Decimal>()?;
+    if step <= Decimal::ZERO {
+        return Err("step must be positive".into());
+    }
+    if start > end {
+        return Err("start must not be greater than end".into());
+    }
+
+    let mut amounts = Vec::new();
+    let mut amount = start;
+    while amount <= end {
+        amounts.push(amount);
+        amount += step;
+    }
+    Ok(amounts)
+}
+
+/// Frames from one TIME directive that mix units and whose longest is more than
+/// ten times the shortest, which often means a mistyped unit (`5d` for `5w`)
+fn mixed_horizon_warning(frames: &[TimeFrame]) -> Option<String> {
+    let shortest = frames.iter().min_by_key(|f| f.approx_days())?;
+    let longest = frames.iter().max_by_key(|f| f.approx_days())?;
+    let mixed_units = frames.iter().any(|f| f.unit != frames[0].unit);
+    if !mixed_units || longest.approx_days() <= shortest.approx_days().saturating_mul(10) {
+        return None;
+    }
+    Some(format!("mixes units spanning {} to {}; check each unit is intended", shortest, longest))
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether a quote character after `previous` starts a quoted value. Quotes
+/// only open at the start of a token, so an apostrophe inside a bare name
+/// such as `McDonald's` stays literal.
+fn opens_quote(c: char, previous: Option<char>) -> bool {
+    (c == '"' || c == '\'') && previous.is_none_or(|p| p.is_whitespace() || p == ',')
+}
+
+/// Cuts a line at the first `//` or `#` outside quotes, so directives can
+/// carry trailing comments while quoted values keep those characters
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut previous = None;
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if opens_quote(c, previous) => quote = Some(c),
+            None if c == '#' => return &line[..index],
+            None if c == '/' && line[index + 1..].starts_with('/') => return &line[..index],
+            None => {}
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+/// Splits a comma-separated list of names. A name wrapped in single or double
+/// quotes is kept exactly as written, commas and spacing included; bare names
+/// are trimmed.
+fn split_names(text: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut names = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut quoted = false;
+    let mut previous = None;
+
+    for c in text.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == ',' => {
+                names.push(if quoted { current.clone() } else { current.trim().to_string() });
+                current.clear();
+                quoted = false;
+            }
+            None if quoted => {
+                if !c.is_whitespace() {
+                    return Err(format!("Unexpected '{}' after quoted name \"{}\"", c, current).into());
+                }
+            }
+            None if current.trim().is_empty() && opens_quote(c, previous) => {
+                current.clear();
+                quote = Some(c);
+                quoted = true;
+            }
+            None => current.push(c),
+        }
+        previous = Some(c);
+    }
+
+    if let Some(q) = quote {
+        return Err(format!("Unterminated {} quote in: {}", q, text).into());
+    }
+    names.push(if quoted { current } else { current.trim().to_string() });
+    Ok(names)
+}
+
+/// A single free-text value: everything given, or exactly one quoted value,
+/// which may hold commas. Bare text has its spacing collapsed.
+fn single_value(text: &str) -> Result<String, Box<dyn std::error::Error>> {
+    if !text.starts_with(['"', '\'']) {
+        return Ok(text.split_whitespace().collect::<Vec<_>>().join(" "));
+    }
+    match split_names(text)?.as_slice() {
+        [value] => Ok(value.clone()),
+        _ => Err(format!("expected a single quoted value: {}", text).into()),
+    }
+}
+
+/// The rest of a line after its first `tokens` whitespace-separated tokens
+fn after_tokens(line: &str, tokens: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..tokens {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest
+}
+
+/// Replaces each `$NAME` in a line with the value of an earlier `SET NAME ...`
+fn substitute_variables(
+    line: &str,
+    variables: &HashMap<String, String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut substituted = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('$') {
+        substituted.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+        let name = &after[..end];
+        if name.is_empty() {
+            return Err("Expected a variable name after '$'".into());
+        }
+        let value = variables.get(name)
+            .ok_or_else(|| format!("Undefined variable ${}", name))?;
+        substituted.push_str(value);
+        rest = &after[end..];
+    }
+    substituted.push_str(rest);
+    Ok(substituted)
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// The single name in `names` that `entry` matches, trying in turn a
+/// case/spacing-insensitive match, a whole-word prefix of either name, and the
+/// closest spelling within `FUZZY_NAME_DISTANCE` edits. On failure returns the
+/// tied candidates of the first step that found any.
+fn fuzzy_match<'a>(entry: &str, names: &'a [String]) -> Result<&'a str, Vec<&'a str>> {
+    let entry = normalize_name(entry);
+    let normalized: Vec<(String, &str)> = names.iter().map(|n| (normalize_name(n), n.as_str())).collect();
+
+    let is_word_prefix = |short: &str, long: &str| {
+        long.strip_prefix(short).is_some_and(|rest| rest.starts_with(' '))
+    };
+    let steps: [&dyn Fn(&str) -> bool; 2] = [
+        &|name| name == entry,
+        &|name| is_word_prefix(&entry, name) || is_word_prefix(name, &entry),
+    ];
+    for step in steps {
+        let candidates: Vec<&str> = normalized.iter().filter(|(n, _)| step(n)).map(|(_, name)| *name).collect();
+        match candidates.as_slice() {
+            [] => continue,
+            [name] => return Ok(name),
+            _ => return Err(candidates),
+        }
+    }
+
+    let Some(best) = normalized.iter().map(|(n, _)| edit_distance(&entry, n)).min() else {
+        return Err(Vec::new());
+    };
+    if best > FUZZY_NAME_DISTANCE {
+        return Err(Vec::new());
+    }
+    let candidates: Vec<&str> = normalized.iter()
+        .filter(|(n, _)| edit_distance(&entry, n) == best)
+        .map(|(_, name)| *name)
+        .collect();
+    match candidates.as_slice() {
+        [name] => Ok(name),
+        _ => Err(candidates),
+    }
+}
+
+/// Levenshtein distance between two strings, by character
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+impl Default for StockDSL {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StockDSL {
@@ -41,69 +466,401 @@ impl StockDSL {
             investments: HashMap::new(),
             patterns: HashMap::new(),
             tests: Vec::new(),
+            rolling_window: None,
+            entry_price: PriceField::default(),
+            prices_file: None,
+            ticker_suffixes: Vec::new(),
+            max_trades: None,
+            benchmark: Vec::new(),
+            vol_target: None,
+            name_match: NameMatch::default(),
+            ewma_lambda: None,
+            initial_deploy: InitialDeploy::default(),
+            price_check: PriceCheck::default(),
+            end_align: EndAlign::default(),
+            withdrawal: None,
+            descriptions: HashMap::new(),
+            pattern_weights: HashMap::new(),
+            return_endpoint: ReturnEndpoint::default(),
+            fee: None,
+            fee_models: Vec::new(),
+            cash_buffer: None,
+            currency: DEFAULT_CURRENCY.to_string(),
+            value_at_risk: None,
+            warnings: Vec::new(),
+            ignored_lines: Vec::new(),
+            included_files: Vec::new(),
         }
     }
 
+    /// Parses a file, resolving INCLUDE paths relative to its directory
     pub fn parse_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
         let content = fs::read_to_string(path)?;
-        Self::parse(&content)
+        let mut dsl = StockDSL::new();
+        let mut including = vec![fs::canonicalize(path)?];
+        dsl.parse_source(&content, path.parent(), &mut HashMap::new(), &mut including)?;
+        Ok(dsl.finish())
     }
 
+    /// Like `parse_file`, but an unknown or incomplete directive is an error
+    pub fn parse_file_strict(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse_file(path)?.strict()
+    }
+
+    /// Like `parse`, but an unknown or incomplete directive is an error
+    pub fn parse_strict(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(content)?.strict()
+    }
+
+    fn strict(self) -> Result<Self, Box<dyn std::error::Error>> {
+        match self.ignored_lines.first() {
+            Some(problem) => Err(problem.clone().into()),
+            None => Ok(self),
+        }
+    }
+
+    /// Parses DSL text, resolving INCLUDE paths relative to the working directory
     pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let mut dsl = StockDSL::new();
-        
-        for line in content.lines() {
-            let line = line.trim();
-            
+        dsl.parse_source(content, None, &mut HashMap::new(), &mut Vec::new())?;
+        Ok(dsl.finish())
+    }
+
+    /// Applies every line of `content`. `including` holds the files currently
+    /// being parsed, outermost first, to catch INCLUDE cycles.
+    fn parse_source(
+        &mut self,
+        content: &str,
+        base_dir: Option<&Path>,
+        variables: &mut HashMap<String, String>,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (index, line) in content.lines().enumerate() {
+            let line = strip_comment(line).trim();
+
             // Skip empty lines and comments
-            if line.is_empty() || line.starts_with("//") {
+            if line.is_empty() {
                 continue;
             }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.is_empty() {
-                continue;
+            let result = match line.split_once(char::is_whitespace) {
+                Some((keyword, target)) if keyword.eq_ignore_ascii_case("INCLUDE") => {
+                    self.include(target.trim(), base_dir, variables, including)
+                }
+                _ => self.parse_line(line, variables, index + 1),
+            };
+            result.map_err(|e| format!("line {} ('{}'): {}", index + 1, line, e))?;
+        }
+        Ok(())
+    }
+
+    /// Parses another file in place, sharing SET variables with the includer.
+    /// Its definitions replace earlier ones with the same ticker or pattern name.
+    fn include(
+        &mut self,
+        target: &str,
+        base_dir: Option<&Path>,
+        variables: &mut HashMap<String, String>,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let target = substitute_variables(target, variables)?;
+        let relative = PathBuf::from(target.trim_matches('"'));
+        let path = match base_dir {
+            Some(dir) => dir.join(&relative),
+            None => relative,
+        };
+        let canonical = fs::canonicalize(&path)
+            .map_err(|e| format!("Failed to read included file {:?}: {}", path, e))?;
+
+        if including.contains(&canonical) {
+            let chain: Vec<String> = including.iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format!("INCLUDE cycle: {}", chain.join(" -> ")).into());
+        }
+
+        let content = fs::read_to_string(&canonical)
+            .map_err(|e| format!("Failed to read included file {:?}: {}", path, e))?;
+        self.included_files.push(canonical.clone());
+        including.push(canonical);
+        let result = self.parse_source(&content, path.parent(), variables, including);
+        including.pop();
+        result.map_err(|e| format!("in {:?}: {}", path, e).into())
+    }
+
+    /// Post-processing once every line, including INCLUDEd ones, is read
+    fn finish(mut self) -> Self {
+        self.resolve_tickers();
+        if self.name_match == NameMatch::Fuzzy {
+            self.resolve_fuzzy_names();
+        }
+        self
+    }
+
+    /// Checks that every TEST and DESCRIBE names a PATTERN and every pattern entry
+    /// matches an INVESTMENT, reporting all problems together rather than the first
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut problems = Vec::new();
+        for test in &self.tests {
+            if !self.patterns.contains_key(test) {
+                problems.push(format!("TEST {} does not match any PATTERN", test));
             }
+        }
 
-            match parts[0] {
-                "INVEST" => {
-                    if parts.len() >= 2 {
-                        dsl.parse_invest(&parts[1..])?;
-                    }
+        let mut described: Vec<&String> = self.descriptions.keys().collect();
+        described.sort();
+        for pattern_name in described {
+            if !self.patterns.contains_key(pattern_name) {
+                problems.push(format!("DESCRIBE {} does not match any PATTERN", pattern_name));
+            }
+        }
+
+        let mut pattern_names: Vec<&String> = self.patterns.keys().collect();
+        pattern_names.sort();
+        for pattern_name in pattern_names {
+            for company in &self.patterns[pattern_name] {
+                if !self.investments.values().any(|inv| inv.name == *company) {
+                    problems.push(format!("PATTERN {} entry '{}' does not match any INVESTMENT name or ticker", pattern_name, company));
                 }
-                "TIME" => {
-                    if parts.len() >= 2 {
-                        dsl.parse_time(&parts[1..])?;
-                    }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n").into())
+        }
+    }
+
+    /// Applies one comment-stripped, non-empty line of the DSL
+    fn parse_line(
+        &mut self,
+        line: &str,
+        variables: &mut HashMap<String, String>,
+        line_number: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let line = substitute_variables(line, variables)?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            return Ok(());
+        }
+
+        // Keywords are case-insensitive; names, tickers and values are not
+        match parts[0].to_ascii_uppercase().as_str() {
+            "SET" if parts.len() >= 3 => {
+                if !is_variable_name(parts[1]) {
+                    return Err(format!("Invalid variable name: {}", parts[1]).into());
                 }
-                "INVESTMENT" => {
-                    if parts.len() >= 3 {
-                        dsl.parse_investment(&parts[1..])?;
-                    }
+                variables.insert(parts[1].to_string(), parts[2..].join(" "));
+            }
+            "INVEST" if parts.len() >= 2 => {
+                self.parse_invest(&parts[1..])?;
+            }
+            "TIME" if parts.len() >= 2 => {
+                let first = self.time_frames.len();
+                self.parse_time(&parts[1..])?;
+                if let Some(warning) = mixed_horizon_warning(&self.time_frames[first..]) {
+                    self.warnings.push(format!("TIME on line {} {}", line_number, warning));
+                }
+            }
+            "INVESTMENT" if parts.len() >= 3 => {
+                self.parse_investment(parts[1], after_tokens(&line, 2))?;
+            }
+            "PATTERN" if parts.len() >= 3 => {
+                self.parse_pattern(parts[1], after_tokens(&line, 2))?;
+            }
+            "DESCRIBE" if parts.len() >= 3 => {
+                let description = single_value(after_tokens(&line, 2))?;
+                self.descriptions.insert(parts[1].to_string(), description);
+            }
+            "TEST" if parts.len() >= 2 => {
+                self.tests.push(parts[1].to_string());
+            }
+            "ROLLING" if parts.len() >= 2 => {
+                let window = self.parse_time_frame(parts[1])?;
+                self.rolling_window = Some(window.total_weeks());
+            }
+            "PRICES" if parts.len() >= 2 => {
+                let path = parts[1..].join(" ");
+                self.prices_file = Some(PathBuf::from(path.trim_matches('"')));
+            }
+            "TICKER_SUFFIXES" if parts.len() >= 2 => {
+                self.ticker_suffixes = parts[1..].join(" ")
+                    .split(',')
+                    .map(|suffix| suffix.trim().to_string())
+                    .filter(|suffix| !suffix.is_empty())
+                    .collect();
+            }
+            "BENCHMARK" if parts.len() >= 2 => {
+                self.parse_benchmark(&parts[1..])?;
+            }
+            "VOL_TARGET" if parts.len() >= 2 => {
+                self.vol_target = Some(parse_fraction(parts[1])?);
+            }
+            "VAR" if parts.len() >= 2 => {
+                let confidence = parse_fraction(parts[1])?;
+                if confidence < Decimal::new(5, 1) || confidence >= Decimal::ONE {
+                    return Err(format!("VAR confidence must be at least 50% and below 100%: {}", parts[1]).into());
                 }
-                "PATTERN" => {
-                    if parts.len() >= 3 {
-                        dsl.parse_pattern(&parts[1..])?;
+                let method = match parts.get(2).map(|m| m.to_lowercase()).as_deref() {
+                    None | Some("historical") => VarMethod::Historical,
+                    Some("parametric") => VarMethod::Parametric,
+                    Some(other) => return Err(format!("Invalid VAR method: {}", other).into()),
+                };
+                self.value_at_risk = Some(VarSpec { confidence, method });
+            }
+            "EWMA_LAMBDA" if parts.len() >= 2 => {
+                let lambda = parts[1].parse::<Decimal>()?;
+                if lambda <= Decimal::ZERO || lambda >= Decimal::ONE {
+                    return Err(format!("EWMA_LAMBDA must be between 0 and 1: {}", lambda).into());
+                }
+                self.ewma_lambda = Some(lambda);
+            }
+            "INITIAL_DEPLOY" if parts.len() >= 2 => {
+                self.initial_deploy = match (parts[1].to_lowercase().as_str(), parts.get(2)) {
+                    ("immediate", _) => InitialDeploy::Immediate,
+                    ("spread", Some(period)) => {
+                        let weeks = self.parse_time_frame(period)?.total_weeks();
+                        if weeks == 0 {
+                            return Err(format!("INITIAL_DEPLOY spread period must be at least a week: {}", period).into());
+                        }
+                        InitialDeploy::Spread(weeks)
                     }
+                    _ => return Err(format!("Invalid initial deployment: {}", parts[1..].join(" ")).into()),
+                };
+            }
+            "PRICE_CHECK" if parts.len() >= 2 => {
+                let max_deviation = parse_fraction(parts[1])?;
+                if max_deviation <= Decimal::ZERO {
+                    return Err(format!("PRICE_CHECK tolerance must be positive: {}", parts[1]).into());
+                }
+                let strict = match parts.get(2).map(|mode| mode.to_lowercase()) {
+                    None => false,
+                    Some(mode) if mode == "strict" => true,
+                    Some(mode) if mode == "warn" => false,
+                    Some(mode) => return Err(format!("Invalid price check mode: {}", mode).into()),
+                };
+                self.price_check = PriceCheck { max_deviation, strict };
+            }
+            "END_ALIGN" if parts.len() >= 2 => {
+                self.end_align = match parts[1].to_lowercase().as_str() {
+                    "partial" => EndAlign::Partial,
+                    "complete" => EndAlign::Complete,
+                    other => return Err(format!("Invalid end alignment: {}", other).into()),
+                };
+            }
+            "WITHDRAW" if parts.len() >= 3 => {
+                let amount = parts[1].parse::<Decimal>()?;
+                if amount <= Decimal::ZERO {
+                    return Err(format!("WITHDRAW amount must be positive: {}", parts[1]).into());
+                }
+                let per_year = match parts[2].to_lowercase().as_str() {
+                    "weekly" => 52,
+                    "monthly" => 12,
+                    "quarterly" => 4,
+                    "yearly" => 1,
+                    other => return Err(format!("Invalid withdrawal frequency: {}", other).into()),
+                };
+                self.withdrawal = Some(Withdrawal { amount, per_year });
+            }
+            "CASH_BUFFER" if parts.len() >= 2 => {
+                let buffer = parse_fraction(parts[1])?;
+                if buffer < Decimal::ZERO || buffer >= Decimal::ONE {
+                    return Err(format!("CASH_BUFFER must be at least 0% and below 100%: {}", parts[1]).into());
+                }
+                self.cash_buffer = Some(buffer);
+            }
+            "DIVIDEND" if parts.len() >= 3 => {
+                let dividend_yield = parse_fraction(parts[2])?;
+                if dividend_yield < Decimal::ZERO {
+                    return Err(format!("DIVIDEND yield can't be negative: {}", parts[2]).into());
                 }
-                "TEST" => {
-                    if parts.len() >= 2 {
-                        dsl.tests.push(parts[1].to_string());
+                let investment = self.investments.get_mut(parts[1])
+                    .ok_or_else(|| format!("DIVIDEND {} must follow the INVESTMENT line for that ticker", parts[1]))?;
+                investment.dividend_yield = Some(dividend_yield);
+            }
+            "FEE" if parts.len() >= 2 => {
+                self.fee = Some(parse_fee("FEE", parts[1])?);
+            }
+            "FEE_MODEL" if parts.len() >= 3 => {
+                let fee = parse_fee("FEE_MODEL", parts[2])?;
+                match self.fee_models.iter_mut().find(|(name, _)| name == parts[1]) {
+                    Some(model) => model.1 = fee,
+                    None => self.fee_models.push((parts[1].to_string(), fee)),
+                }
+            }
+            "RETURN_ENDPOINT" if parts.len() >= 2 => {
+                self.return_endpoint = match parts[1].to_lowercase().as_str() {
+                    "raw" => ReturnEndpoint::Raw,
+                    "trimmed" => {
+                        let bars = match parts.get(2) {
+                            Some(bars) => bars.parse::<usize>()?,
+                            None => DEFAULT_TRIMMED_BARS,
+                        };
+                        if bars < 2 {
+                            return Err(format!("RETURN_ENDPOINT trimmed needs at least 2 bars: {}", bars).into());
+                        }
+                        ReturnEndpoint::Trimmed(bars)
                     }
+                    other => return Err(format!("Invalid return endpoint: {}", other).into()),
+                };
+            }
+            "MAX_TRADES" if parts.len() >= 2 => {
+                let max_trades = parts[1].parse::<usize>()?;
+                if max_trades == 0 {
+                    return Err("MAX_TRADES must be at least 1; every simulation records its first trade".into());
                 }
-                _ => {
-                    // Ignore unrecognized commands
+                self.max_trades = Some(max_trades);
+            }
+            "ENTRY_PRICE" if parts.len() >= 2 => {
+                self.entry_price = match parts[1].to_lowercase().as_str() {
+                    "open" => PriceField::Open,
+                    "high" => PriceField::High,
+                    "low" => PriceField::Low,
+                    "close" => PriceField::Close,
+                    other => return Err(format!("Invalid entry price field: {}", other).into()),
+                };
+            }
+            "CURRENCY" if parts.len() >= 2 => {
+                if !parts[1].chars().all(|c| c.is_ascii_alphabetic()) {
+                    return Err(format!("Invalid currency code: {}", parts[1]).into());
                 }
+                self.currency = parts[1].to_ascii_uppercase();
+            }
+            "NAME_MATCH" if parts.len() >= 2 => {
+                self.name_match = match parts[1].to_lowercase().as_str() {
+                    "exact" => NameMatch::Exact,
+                    "fuzzy" => NameMatch::Fuzzy,
+                    other => return Err(format!("Invalid name match mode: {}", other).into()),
+                };
+            }
+            keyword => {
+                // Skipped, but reported so a typo doesn't silently change the run
+                let problem = if KEYWORDS.contains(&keyword) {
+                    format!("{} on line {} is missing arguments", keyword, line_number)
+                } else {
+                    format!("unknown command '{}' on line {}", parts[0], line_number)
+                };
+                self.warnings.push(format!("{}; line ignored", problem));
+                self.ignored_lines.push(problem);
             }
         }
-
-        Ok(dsl)
+        Ok(())
     }
 
     fn parse_invest(&mut self, parts: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
         let amounts_str = parts.join(" ");
         for amount_str in amounts_str.split(',') {
-            let amount = amount_str.trim().parse::<Decimal>()?;
+            let amount_str = amount_str.trim();
+            if amount_str.contains("..") {
+                let range = parse_amount_range(amount_str)
+                    .map_err(|e| format!("Invalid invest range '{}': {}", amount_str, e))?;
+                self.invest_amounts.extend(range);
+                continue;
+            }
+            let amount = amount_str.parse::<Decimal>()
+                .map_err(|e| format!("Invalid invest amount '{}': {}", amount_str, e))?;
             self.invest_amounts.push(amount);
         }
         Ok(())
@@ -119,53 +876,545 @@ impl StockDSL {
         Ok(())
     }
 
+    /// Parses a single term such as `30d` or a compound one such as `1y6m` or `2w3d`.
+    /// Compound terms are summed into the smallest unit that represents them exactly:
+    /// months for years+months, weeks for years+weeks, otherwise days
+    /// (a year counting as 365 days and a month as 30).
     fn parse_time_frame(&self, time_str: &str) -> Result<TimeFrame, Box<dyn std::error::Error>> {
-        let len = time_str.len();
-        if len < 2 {
-            return Err("Invalid time format".into());
-        }
-
-        let (number_part, unit_part) = time_str.split_at(len - 1);
-        let duration = number_part.parse::<u32>()?;
-        
-        let unit = match unit_part {
-            "d" => TimeUnit::Days,
-            "w" => TimeUnit::Weeks,
-            "y" => TimeUnit::Years,
-            _ => return Err(format!("Invalid time unit: {}", unit_part).into()),
+        if time_str.len() < 2 {
+            return Err(format!("Invalid time format: '{}'", time_str).into());
+        }
+
+        let mut components = Vec::new();
+        let mut number_part = String::new();
+        for c in time_str.chars() {
+            if c.is_ascii_digit() {
+                number_part.push(c);
+                continue;
+            }
+
+            if number_part.is_empty() {
+                return Err(format!("Invalid time format: '{}'", time_str).into());
+            }
+            let duration = number_part.parse::<u32>()
+                .map_err(|e| format!("Invalid duration '{}' in '{}': {}", number_part, time_str, e))?;
+            number_part.clear();
+
+            let unit = match c {
+                'd' => TimeUnit::Days,
+                'w' => TimeUnit::Weeks,
+                'm' => TimeUnit::Months,
+                'y' => TimeUnit::Years,
+                _ => return Err(format!("Invalid time unit '{}' in '{}'", c, time_str).into()),
+            };
+            components.push(TimeFrame { duration, unit });
+        }
+
+        if !number_part.is_empty() {
+            return Err(format!("Missing time unit in '{}'", time_str).into());
+        }
+
+        let too_long = || format!("Time period '{}' is too long", time_str);
+        if components.len() == 1 {
+            let frame = components.remove(0);
+            // total_weeks() scales years and months by 52
+            if matches!(frame.unit, TimeUnit::Years | TimeUnit::Months) && frame.duration.checked_mul(52).is_none() {
+                return Err(too_long().into());
+            }
+            return Ok(frame);
+        }
+
+        let has_unit = |unit: TimeUnit| components.iter().any(|c| c.unit == unit);
+        let unit = if !has_unit(TimeUnit::Days) && !has_unit(TimeUnit::Weeks) {
+            TimeUnit::Months
+        } else if !has_unit(TimeUnit::Days) && !has_unit(TimeUnit::Months) {
+            TimeUnit::Weeks
+        } else {
+            TimeUnit::Days
+        };
+
+        let per_unit = |component: &TimeUnit| match (&unit, component) {
+            (TimeUnit::Months, TimeUnit::Years) => 12,
+            (TimeUnit::Weeks, TimeUnit::Years) => 52,
+            (TimeUnit::Days, TimeUnit::Weeks) => 7,
+            (TimeUnit::Days, TimeUnit::Months) => 30,
+            (TimeUnit::Days, TimeUnit::Years) => 365,
+            _ => 1,
         };
 
+        let duration = components.iter()
+            .try_fold(0u32, |total, c| {
+                c.duration.checked_mul(per_unit(&c.unit)).and_then(|d| total.checked_add(d))
+            })
+            .filter(|&d| unit != TimeUnit::Months || d.checked_mul(52).is_some())
+            .ok_or_else(too_long)?;
         Ok(TimeFrame { duration, unit })
     }
 
-    fn parse_investment(&mut self, parts: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
-        if parts.len() >= 2 {
-            let ticker = parts[0].to_string();
-            let name = parts[1..].join(" ");
-            
-            let investment = Investment {
-                ticker: ticker.clone(),
-                name,
-                price: None, // Will be fetched later
+    /// `BENCHMARK SPY` or a blend such as `BENCHMARK 0.6 SPY, 0.4 AGG`
+    fn parse_benchmark(&mut self, parts: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        self.benchmark.clear();
+        for leg in parts.join(" ").split(',') {
+            let tokens: Vec<&str> = leg.split_whitespace().collect();
+            let (weight, ticker) = match tokens.as_slice() {
+                [ticker] => (Decimal::ONE, *ticker),
+                [weight, ticker] => (weight.parse::<Decimal>()?, *ticker),
+                _ => return Err(format!("Invalid benchmark leg: {}", leg.trim()).into()),
             };
-            
-            self.investments.insert(ticker, investment);
+            if weight <= Decimal::ZERO {
+                return Err(format!("Benchmark weight must be positive: {}", leg.trim()).into());
+            }
+            self.benchmark.push((ticker.to_string(), weight));
         }
         Ok(())
     }
 
-    fn parse_pattern(&mut self, parts: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
-        if parts.len() >= 2 {
-            let pattern_name = parts[0].to_string();
-            let companies_str = parts[1..].join(" ");
-            
-            let mut companies = Vec::new();
-            for company in companies_str.split(',') {
-                companies.push(company.trim().to_string());
+    fn parse_investment(&mut self, ticker: &str, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let name = single_value(name)
+            .map_err(|e| format!("INVESTMENT {}: {}", ticker, e))?;
+
+        let investment = Investment {
+            ticker: ticker.to_string(),
+            name,
+            dividend_yield: None,
+        };
+
+        self.investments.insert(ticker.to_string(), investment);
+        Ok(())
+    }
+
+    /// Rewrites pattern entries that give an investment's ticker (`AAPL`) rather
+    /// than its name to that name, which is what the simulator looks up
+    fn resolve_tickers(&mut self) {
+        for companies in self.patterns.values_mut() {
+            for company in companies.iter_mut() {
+                if self.investments.values().any(|inv| inv.name == *company) {
+                    continue;
+                }
+                if let Some(investment) = self.investments.get(company.as_str()) {
+                    *company = investment.name.clone();
+                }
+            }
+        }
+    }
+
+    /// Rewrites pattern entries that don't name an investment exactly to the one
+    /// investment they fuzzily match, warning about each substitution. Entries
+    /// with no match or several equally good ones are left for the simulator to
+    /// reject.
+    fn resolve_fuzzy_names(&mut self) {
+        let mut names: Vec<String> = self.investments.values().map(|inv| inv.name.clone()).collect();
+        names.sort();
+
+        let mut pattern_names: Vec<String> = self.patterns.keys().cloned().collect();
+        pattern_names.sort();
+        for pattern_name in pattern_names {
+            let Some(companies) = self.patterns.get_mut(&pattern_name) else {
+                continue;
+            };
+            for company in companies.iter_mut() {
+                if names.contains(company) {
+                    continue;
+                }
+                match fuzzy_match(company, &names) {
+                    Ok(name) => {
+                        self.warnings.push(format!("pattern {} entry '{}' matched investment '{}'", pattern_name, company, name));
+                        *company = name.to_string();
+                    }
+                    Err(candidates) if candidates.len() > 1 => {
+                        self.warnings.push(format!("pattern {} entry '{}' is ambiguous between {}",
+                            pattern_name, company, candidates.join(", ")));
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+
+    /// Replaces the TEST list with `patterns`, in order and without repeats.
+    /// Leaves the list untouched and returns the first name that isn't a PATTERN.
+    pub fn restrict_tests(&mut self, patterns: &[String]) -> Result<(), String> {
+        if let Some(unknown) = patterns.iter().find(|name| !self.patterns.contains_key(*name)) {
+            return Err(unknown.clone());
+        }
+        self.tests.clear();
+        for name in patterns {
+            if !self.tests.contains(name) {
+                self.tests.push(name.clone());
             }
-            
-            self.patterns.insert(pattern_name, companies);
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Weight of each entry of a pattern, in pattern order; 1 for every entry of
+    /// an unweighted pattern
+    pub fn entry_weights(&self, pattern_name: &str) -> Vec<Decimal> {
+        match self.pattern_weights.get(pattern_name) {
+            Some(weights) => weights.clone(),
+            None => vec![Decimal::ONE; self.patterns.get(pattern_name).map_or(0, Vec::len)],
+        }
+    }
+
+    /// `PATTERN name A,B` or, with weights, `PATTERN name A:0.6, B:0.4`
+    fn parse_pattern(&mut self, pattern_name: &str, companies: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut names = Vec::new();
+        let mut weights = Vec::new();
+        for entry in split_names(companies)? {
+            let weighted = entry.rsplit_once(':')
+                .and_then(|(name, weight)| Some((name.trim(), weight.trim().parse::<Decimal>().ok()?)));
+            match weighted {
+                Some((name, weight)) => {
+                    if weight < Decimal::ZERO {
+                        return Err(format!("Weight of {} can't be negative: {}", name, weight).into());
+                    }
+                    names.push(name.to_string());
+                    weights.push(weight);
+                }
+                None => names.push(entry),
+            }
+        }
+
+        if weights.is_empty() {
+            self.pattern_weights.remove(pattern_name);
+        } else {
+            if weights.len() != names.len() {
+                return Err(format!("PATTERN {} gives weights for only some entries", pattern_name).into());
+            }
+            let total: Decimal = weights.iter().sum();
+            if total <= Decimal::ZERO {
+                return Err(format!("PATTERN {} weights sum to zero", pattern_name).into());
+            }
+            if total != Decimal::ONE {
+                self.warnings.push(format!("PATTERN {} weights sum to {}, not 1; they are used in proportion", pattern_name, total));
+            }
+            self.pattern_weights.insert(pattern_name.to_string(), weights);
+        }
+        self.patterns.insert(pattern_name.to_string(), names);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(time: &str) -> Result<Vec<(u32, TimeUnit)>, String> {
+        StockDSL::parse(&format!("TIME {}", time))
+            .map(|dsl| dsl.time_frames.into_iter().map(|t| (t.duration, t.unit)).collect())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn compound_time_terms_sum_into_the_smallest_exact_unit() {
+        assert_eq!(frames("1y6m").unwrap(), vec![(18, TimeUnit::Months)]);
+        assert_eq!(frames("2w3d").unwrap(), vec![(17, TimeUnit::Days)]);
+        assert_eq!(frames("1y2w").unwrap(), vec![(54, TimeUnit::Weeks)]);
+    }
+
+    #[test]
+    fn oversized_time_terms_are_a_parse_error() {
+        assert!(frames("400000000y1m").unwrap_err().contains("too long"));
+        assert!(frames("400000000y").unwrap_err().contains("too long"));
+        assert!(frames("4000000000w1d").unwrap_err().contains("too long"));
+    }
+
+    #[test]
+    fn fuzzy_name_match_resolves_only_what_exact_matching_leaves() {
+        let source = |mode: &str| format!(
+            "NAME_MATCH {}\nINVESTMENT AAPL Apple Inc\nINVESTMENT MSFT Microsoft\nINVESTMENT A1 Alpha One\nINVESTMENT A2 Alpha Two\nPATTERN p apple, Mircosoft, Microsoft, Tesla, Alpha",
+            mode,
+        );
+
+        let exact = StockDSL::parse(&source("exact")).unwrap();
+        assert_eq!(exact.patterns["p"], ["apple", "Mircosoft", "Microsoft", "Tesla", "Alpha"]);
+
+        let fuzzy = StockDSL::parse(&source("fuzzy")).unwrap();
+        assert_eq!(fuzzy.patterns["p"], ["Apple Inc", "Microsoft", "Microsoft", "Tesla", "Alpha"]);
+        assert!(fuzzy.warnings.iter().any(|w| w.contains("'Mircosoft' matched investment 'Microsoft'")));
+        assert!(fuzzy.warnings.iter().any(|w| w.contains("'Alpha' is ambiguous between Alpha One, Alpha Two")));
+    }
+
+    #[test]
+    fn only_restricts_tests_to_known_patterns() {
+        let mut dsl = StockDSL::parse(
+            "INVESTMENT AAPL Apple\nPATTERN a Apple\nPATTERN b Apple\nPATTERN c Apple\nTEST a\nTEST b",
+        ).unwrap();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(dsl.restrict_tests(&names(&["c", "a", "c"])), Ok(()));
+        assert_eq!(dsl.tests, ["c", "a"]);
+
+        assert_eq!(dsl.restrict_tests(&names(&["b", "z"])), Err("z".to_string()));
+        assert_eq!(dsl.tests, ["c", "a"]);
+    }
+
+    #[test]
+    fn undefined_variables_are_reported_with_their_line() {
+        let dsl = StockDSL::parse("SET AMOUNT 1000, 2000\nINVEST $AMOUNT\nTIME 1y").unwrap();
+        assert_eq!(dsl.invest_amounts, [Decimal::from(1000), Decimal::from(2000)]);
+
+        let error = StockDSL::parse("SET AMOUNT 1000\nINVEST $AMOUNT\n\nTIME $HORIZON").unwrap_err().to_string();
+        assert!(error.contains("line 4"), "{}", error);
+        assert!(error.contains("Undefined variable $HORIZON"), "{}", error);
+    }
+
+    #[test]
+    fn variables_expand_into_pattern_company_lists() {
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nINVESTMENT GOOG Alphabet\nINVESTMENT TLT Treasuries\n\
+             SET GROWTH AAPL, MSFT, GOOG\nPATTERN p1 $GROWTH\nPATTERN p2 $GROWTH, TLT",
+        ).unwrap();
+        assert_eq!(dsl.patterns["p1"], ["Apple", "Microsoft", "Alphabet"]);
+        assert_eq!(dsl.patterns["p2"], ["Apple", "Microsoft", "Alphabet", "Treasuries"]);
+    }
+
+    #[test]
+    fn redefined_variables_take_their_latest_value() {
+        let dsl = StockDSL::parse("SET A 1000\nINVEST $A\nSET A 2000\nINVEST $A\nSET B 500\nSET B 750\nINVEST $B").unwrap();
+        assert_eq!(dsl.invest_amounts, [Decimal::from(1000), Decimal::from(2000), Decimal::from(750)]);
+
+        // Substitution happens before quoting is read, so a value can carry quoted names
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nINVESTMENT BRK \"Berkshire, Class B\"\nINVESTMENT KO Coke\n\
+             SET HOLDINGS \"Berkshire, Class B\", Coke\nPATTERN value $HOLDINGS\nTEST value",
+        ).unwrap();
+        assert_eq!(dsl.patterns["value"], ["Berkshire, Class B", "Coke"]);
+        assert!(dsl.validate().is_ok());
+    }
+
+    #[test]
+    fn mixed_horizons_warn_only_across_units() {
+        let warned = |time: &str| {
+            let dsl = StockDSL::parse(&format!("TIME {}", time)).unwrap();
+            dsl.warnings.iter().any(|w| w.contains("mixes units"))
+        };
+
+        assert!(warned("5d, 2y"));
+        assert!(!warned("1y, 2y"));
+        assert!(!warned("1y, 20y"));
+        assert!(!warned("6w, 1y"));
+        // Far past u32 days, but still compared without overflow
+        assert!(warned("5d, 20000000y"));
+    }
+
+    #[test]
+    fn comments_are_stripped_outside_quotes() {
+        assert_eq!(strip_comment("# whole line"), "");
+        assert_eq!(strip_comment("INVEST 1000, 2000  // baseline amounts"), "INVEST 1000, 2000  ");
+        assert_eq!(strip_comment("INVEST 1000 # baseline"), "INVEST 1000 ");
+        assert_eq!(strip_comment(r#"INVESTMENT X "Fund #1 // A" # note"#), r#"INVESTMENT X "Fund #1 // A" "#);
+        assert_eq!(strip_comment("INVESTMENT MCD McDonald's # note"), "INVESTMENT MCD McDonald's ");
+
+        let dsl = StockDSL::parse("# header\nINVEST 1000, 2000  # baseline\n// another\nINVESTMENT X \"Fund #1\"").unwrap();
+        assert_eq!(dsl.invest_amounts, [Decimal::from(1000), Decimal::from(2000)]);
+        assert_eq!(dsl.investments["X"].name, "Fund #1");
+
+        // Commas in a comment aren't more amounts
+        let dsl = StockDSL::parse("INVEST 1000, 2000 # was 500, 750\nTIME 4w, 8w // or 1y, 2y").unwrap();
+        assert_eq!(dsl.invest_amounts, [Decimal::from(1000), Decimal::from(2000)]);
+        assert_eq!(dsl.time_frames.len(), 2);
+    }
+
+    #[test]
+    fn quoted_names_keep_commas_and_spacing() {
+        assert_eq!(split_names(r#""Apple Inc", 'Microsoft, Corp',  Tesla "#).unwrap(), ["Apple Inc", "Microsoft, Corp", "Tesla"]);
+        assert_eq!(split_names(r#"" Padded ""#).unwrap(), [" Padded "]);
+        assert!(split_names(r#""Apple Inc"#).is_err());
+        assert!(split_names(r#""Apple" Inc"#).is_err());
+
+        let dsl = StockDSL::parse(
+            "INVESTMENT AAPL \"Apple, Inc\"\nINVESTMENT MSFT 'Microsoft TEST Corp'\nPATTERN tech \"Apple, Inc\", 'Microsoft TEST Corp'",
+        ).unwrap();
+        assert_eq!(dsl.investments["AAPL"].name, "Apple, Inc");
+        assert_eq!(dsl.patterns["tech"], ["Apple, Inc", "Microsoft TEST Corp"]);
+    }
+
+    #[test]
+    fn parse_errors_name_the_line_and_its_text() {
+        let error = StockDSL::parse("INVEST 1000\n// note\nTIME 3q").unwrap_err().to_string();
+        assert!(error.contains("line 3"), "{}", error);
+        assert!(error.contains("Invalid time unit 'q' in '3q'"), "{}", error);
+
+        let error = StockDSL::parse("TIME 1y\nINVEST 1000, lots").unwrap_err().to_string();
+        assert!(error.contains("line 2"), "{}", error);
+        assert!(error.contains("lots"), "{}", error);
+    }
+
+    #[test]
+    fn validate_reports_every_problem_at_once() {
+        let dsl = StockDSL::parse(
+            "INVESTMENT AAPL Apple\nPATTERN p Apple, Aple\nPATTERN q Apple\nTEST p\nTEST missing\nDESCRIBE gone \"no pattern\"",
+        ).unwrap();
+        assert_eq!(
+            dsl.validate().unwrap_err().to_string(),
+            "TEST missing does not match any PATTERN\n\
+             DESCRIBE gone does not match any PATTERN\n\
+             PATTERN p entry 'Aple' does not match any INVESTMENT name or ticker",
+        );
+
+        let dsl = StockDSL::parse("INVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p").unwrap();
+        assert!(dsl.validate().is_ok());
+    }
+
+    /// A fresh directory under the system temp dir for one test's files
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stock-dsl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn include_merges_files_relative_to_the_includer() {
+        let dir = scratch_dir("include");
+        fs::create_dir_all(dir.join("universe/sectors")).unwrap();
+        fs::write(dir.join("universe/all.stock"), "INVESTMENT AAPL Apple\nINCLUDE sectors/tech.stock").unwrap();
+        fs::write(dir.join("universe/sectors/tech.stock"), "INVESTMENT MSFT Microsoft").unwrap();
+        fs::write(dir.join("amounts.stock"), "INVEST 1000").unwrap();
+        fs::write(
+            dir.join("main.stock"),
+            "INCLUDE amounts.stock\nINCLUDE \"universe/all.stock\"\nINVESTMENT AAPL \"Apple Inc\"\nPATTERN p Apple Inc, Microsoft",
+        ).unwrap();
+
+        let dsl = StockDSL::parse_file(&dir.join("main.stock")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(dsl.invest_amounts, [Decimal::from(1000)]);
+        assert_eq!(dsl.investments["MSFT"].name, "Microsoft");
+        // The includer's later definition wins
+        assert_eq!(dsl.investments["AAPL"].name, "Apple Inc");
+        let included: Vec<_> = dsl.included_files.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(included, ["amounts.stock", "all.stock", "tech.stock"]);
+    }
+
+    #[test]
+    fn include_cycles_are_an_error() {
+        let dir = scratch_dir("cycle");
+        fs::write(dir.join("a.stock"), "INVEST 1000\nINCLUDE b.stock").unwrap();
+        fs::write(dir.join("b.stock"), "INCLUDE a.stock").unwrap();
+
+        let error = StockDSL::parse_file(&dir.join("a.stock")).unwrap_err().to_string();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(error.contains("INCLUDE cycle: "), "{}", error);
+        assert!(error.contains("a.stock -> ") && error.ends_with("a.stock"), "{}", error);
+    }
+
+    #[test]
+    fn return_endpoint_parses_raw_and_trimmed() {
+        let parsed = |line: &str| StockDSL::parse(&format!("INVEST 1000\n{}", line)).map(|dsl| dsl.return_endpoint);
+        assert_eq!(parsed("").unwrap(), ReturnEndpoint::Raw);
+        assert_eq!(parsed("RETURN_ENDPOINT raw").unwrap(), ReturnEndpoint::Raw);
+        assert_eq!(parsed("RETURN_ENDPOINT trimmed").unwrap(), ReturnEndpoint::Trimmed(DEFAULT_TRIMMED_BARS));
+        assert_eq!(parsed("RETURN_ENDPOINT TRIMMED 3").unwrap(), ReturnEndpoint::Trimmed(3));
+        assert!(parsed("RETURN_ENDPOINT trimmed 1").is_err());
+        assert!(parsed("RETURN_ENDPOINT median").is_err());
+    }
+
+    #[test]
+    fn pattern_weights_parse_and_validate() {
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN bal Apple:0.6, Microsoft:0.4\n\
+             PATTERN lopsided Apple:3, Microsoft:1\nPATTERN even Apple, Microsoft",
+        ).unwrap();
+        assert_eq!(dsl.patterns["bal"], ["Apple", "Microsoft"]);
+        assert_eq!(dsl.entry_weights("bal"), [Decimal::new(6, 1), Decimal::new(4, 1)]);
+        assert_eq!(dsl.entry_weights("even"), [Decimal::ONE, Decimal::ONE]);
+        // Weights not summing to 1 only warn
+        assert_eq!(dsl.entry_weights("lopsided"), [Decimal::from(3), Decimal::ONE]);
+        assert_eq!(dsl.warnings, ["PATTERN lopsided weights sum to 4, not 1; they are used in proportion"]);
+
+        let error = |pattern: &str| StockDSL::parse(&format!("INVEST 1000\nPATTERN p {}", pattern)).unwrap_err().to_string();
+        assert!(error("Apple:-0.5, Microsoft:1.5").ends_with("Weight of Apple can't be negative: -0.5"));
+        assert!(error("Apple:0.6, Microsoft").ends_with("PATTERN p gives weights for only some entries"));
+        assert!(error("Apple:0, Microsoft:0").ends_with("PATTERN p weights sum to zero"));
+    }
+
+    #[test]
+    fn keywords_parse_in_any_case() {
+        let upper = StockDSL::parse(
+            "INVEST 1000, 2000\nTIME 4w\nENTRY_PRICE OPEN\nFEE 1%\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\n\
+             PATTERN Growth Apple, Microsoft\nTEST Growth",
+        ).unwrap();
+        let lower = StockDSL::parse(
+            "invest 1000, 2000\nTime 4w\nentry_price open\nfee 1%\ninvestment AAPL Apple\nInvestment MSFT Microsoft\n\
+             pattern Growth Apple, Microsoft\ntest Growth",
+        ).unwrap();
+
+        assert_eq!(lower.invest_amounts, upper.invest_amounts);
+        assert_eq!(lower.time_frames.iter().map(TimeFrame::to_string).collect::<Vec<_>>(), ["4w"]);
+        assert_eq!(lower.entry_price, PriceField::Open);
+        assert_eq!(lower.entry_price, upper.entry_price);
+        assert_eq!(lower.fee, upper.fee);
+        assert_eq!(lower.investments["MSFT"].name, upper.investments["MSFT"].name);
+        assert_eq!(lower.patterns, upper.patterns);
+        assert_eq!(lower.tests, ["Growth"]);
+
+        // Names stay case-sensitive
+        assert!(StockDSL::parse("INVEST 1000\nINVESTMENT AAPL Apple\nPATTERN p apple\nTEST p").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn strict_parsing_rejects_what_lenient_parsing_warns_about() {
+        let source = "INVEST 1000\nINVSET 2000\nTIME 4w\nFEE";
+
+        let lenient = StockDSL::parse(source).unwrap();
+        assert_eq!(lenient.invest_amounts, [Decimal::from(1000)]);
+        assert_eq!(lenient.ignored_lines, ["unknown command 'INVSET' on line 2", "FEE on line 4 is missing arguments"]);
+        assert_eq!(lenient.warnings, [
+            "unknown command 'INVSET' on line 2; line ignored",
+            "FEE on line 4 is missing arguments; line ignored",
+        ]);
+
+        let error = StockDSL::parse_strict(source).unwrap_err();
+        assert_eq!(error.to_string(), "unknown command 'INVSET' on line 2");
+        assert!(StockDSL::parse_strict("INVEST 1000\nTIME 4w").is_ok());
+    }
+
+    #[test]
+    fn invest_ranges_expand_inclusively() {
+        let amounts = |values: &[i64]| values.iter().map(|&v| Decimal::from(v)).collect::<Vec<_>>();
+        assert_eq!(parse_amount_range("1000..5000 step 1000").unwrap(), amounts(&[1000, 2000, 3000, 4000, 5000]));
+        assert_eq!(parse_amount_range("1000..4500 STEP 1000").unwrap(), amounts(&[1000, 2000, 3000, 4000]));
+        assert_eq!(parse_amount_range("500..500 step 100").unwrap(), amounts(&[500]));
+        assert_eq!(parse_amount_range("1000..5000 step 0").unwrap_err().to_string(), "step must be positive");
+        assert_eq!(parse_amount_range("5000..1000 step 1000").unwrap_err().to_string(), "start must not be greater than end");
+        assert!(parse_amount_range("1000..5000").is_err());
+
+        let dsl = StockDSL::parse("INVEST 250, 1000..3000 step 1000, 10000").unwrap();
+        assert_eq!(dsl.invest_amounts, amounts(&[250, 1000, 2000, 3000, 10000]));
+        let error = StockDSL::parse("INVEST 1000..3000 step 0").unwrap_err().to_string();
+        assert!(error.ends_with("Invalid invest range '1000..3000 step 0': step must be positive"), "{}", error);
+    }
+
+    #[test]
+    fn pattern_entries_may_name_a_ticker_or_an_investment() {
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nINVESTMENT GOOG Alphabet\n\
+             PATTERN mixed AAPL, Microsoft, GOOG\nTEST mixed",
+        ).unwrap();
+        assert_eq!(dsl.patterns["mixed"], ["Apple", "Microsoft", "Alphabet"]);
+        assert!(dsl.validate().is_ok());
+
+        // A name that is also some other investment's ticker keeps its name meaning
+        let dsl = StockDSL::parse("INVEST 1000\nINVESTMENT AAPL Apple\nINVESTMENT APPL AAPL\nPATTERN p AAPL").unwrap();
+        assert_eq!(dsl.patterns["p"], ["AAPL"]);
+
+        let dsl = StockDSL::parse("INVEST 1000\nINVESTMENT AAPL Apple\nPATTERN p AAPL, TSLA\nTEST p").unwrap();
+        assert_eq!(
+            dsl.validate().unwrap_err().to_string(),
+            "PATTERN p entry 'TSLA' does not match any INVESTMENT name or ticker",
+        );
+    }
+
+    #[test]
+    fn fee_models_keep_file_order_and_take_their_latest_fee() {
+        let dsl = StockDSL::parse("FEE 1%\nFEE_MODEL discount 4.95\nFEE_MODEL advisor 1%\nFEE_MODEL discount 0").unwrap();
+        assert_eq!(dsl.fee, Some(Fee::Percentage(Decimal::new(1, 2))));
+        assert_eq!(dsl.fee_models, [
+            ("discount".to_string(), Fee::Flat(Decimal::ZERO)),
+            ("advisor".to_string(), Fee::Percentage(Decimal::new(1, 2))),
+        ]);
+
+        let negative = StockDSL::parse_strict("FEE_MODEL rebate -1").unwrap_err().to_string();
+        assert!(negative.ends_with("FEE_MODEL can't be negative: -1"), "{}", negative);
+    }
+}
@@ -0,0 +1,365 @@
+use rust_decimal::Decimal;
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+/// Number formatting conventions for printed output
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Locale {
+    #[default]
+    EnUs, // 1,234.56
+    DeDe, // 1.234,56
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en-US" | "en_US" | "en" => Ok(Locale::EnUs),
+            "de-DE" | "de_DE" | "de" => Ok(Locale::DeDe),
+            _ => Err(format!("Unsupported locale: {} (expected en-US or de-DE)", s)),
+        }
+    }
+}
+
+impl Locale {
+    /// (decimal separator, thousands separator)
+    fn separators(&self) -> (char, char) {
+        match self {
+            Locale::EnUs => ('.', ','),
+            Locale::DeDe => (',', '.'),
+        }
+    }
+
+    /// Formats a number rounded to `precision` decimal places with locale separators
+    pub fn format_number(&self, value: Decimal, precision: usize) -> String {
+        let (decimal_sep, group_sep) = self.separators();
+        let plain = plain_number(value, precision);
+
+        let (sign, unsigned) = match plain.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", plain.as_str()),
+        };
+        let (int_part, frac_part) = match unsigned.split_once('.') {
+            Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+            None => (unsigned, None),
+        };
+
+        let mut grouped = String::new();
+        for (i, digit) in int_part.chars().enumerate() {
+            if i > 0 && (int_part.len() - i) % 3 == 0 {
+                grouped.push(group_sep);
+            }
+            grouped.push(digit);
+        }
+
+        match frac_part {
+            Some(frac) => format!("{}{}{}{}", sign, grouped, decimal_sep, frac),
+            None => format!("{}{}", sign, grouped),
+        }
+    }
+}
+
+/// Rounds to `precision` decimal places with no grouping and `.` as the
+/// decimal separator, never printing `-0`
+pub fn plain_number(value: Decimal, precision: usize) -> String {
+    let rounded = value.round_dp(precision as u32);
+    let rounded = if rounded.is_zero() { Decimal::ZERO } else { rounded };
+    format!("{:.*}", precision, rounded)
+}
+
+/// Layout of printed results
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Text,      // Multi-line report per result
+    Oneline,   // One `result key=value ...` line per result for logs
+    BrokerCsv, // Trades only, as a broker transaction-import CSV
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "oneline" => Ok(OutputFormat::Oneline),
+            "broker-csv" => Ok(OutputFormat::BrokerCsv),
+            _ => Err(format!("Unsupported format: {} (expected text, oneline or broker-csv)", s)),
+        }
+    }
+}
+
+/// When to color gains green and losses red
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorMode {
+    #[default]
+    Auto, // Only when stdout is a terminal and NO_COLOR is unset
+    Always,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!("Unsupported color mode: {} (expected auto, always or never)", s)),
+        }
+    }
+}
+
+impl ColorMode {
+    /// Whether output should carry color codes
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorMode::Auto => std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// Decimal places printed for each class of metric
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Precision {
+    pub money: usize,
+    pub percent: usize,
+    pub shares: usize,
+    pub ratio: usize, // Correlations and other unitless statistics
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision { money: 2, percent: 2, shares: 4, ratio: 2 }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    /// Parses `class=places` pairs such as `shares=6,ratio=3`; classes not
+    /// mentioned keep their default precision
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut precision = Precision::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (class, places) = pair.split_once('=')
+                .ok_or_else(|| format!("Invalid precision '{}' (expected class=places)", pair))?;
+            let places: usize = places.trim().parse()
+                .map_err(|_| format!("Invalid decimal places in '{}'", pair))?;
+            if places > 10 {
+                return Err(format!("Precision for {} must be at most 10 places", class.trim()));
+            }
+            match class.trim() {
+                "money" => precision.money = places,
+                "percent" => precision.percent = places,
+                "shares" => precision.shares = places,
+                "ratio" => precision.ratio = places,
+                other => return Err(format!(
+                    "Unknown metric class: {} (expected money, percent, shares or ratio)", other,
+                )),
+            }
+        }
+        Ok(precision)
+    }
+}
+
+/// A dimension of the simulation sweep that results can be ordered by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    Pattern, // In TEST order
+    Amount,  // In INVEST order
+    Frame,   // In TIME order
+}
+
+/// Order in which results are printed, outermost dimension first
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultOrder(pub Vec<Dimension>);
+
+impl Default for ResultOrder {
+    /// The order simulations run in
+    fn default() -> Self {
+        ResultOrder(vec![Dimension::Pattern, Dimension::Amount, Dimension::Frame])
+    }
+}
+
+impl FromStr for ResultOrder {
+    type Err = String;
+
+    /// Parses a list such as `frame,pattern`; dimensions not mentioned follow
+    /// in their default order
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut order = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let dimension = match name {
+                "pattern" => Dimension::Pattern,
+                "amount" => Dimension::Amount,
+                "frame" => Dimension::Frame,
+                other => return Err(format!(
+                    "Unknown dimension: {} (expected pattern, amount or frame)", other,
+                )),
+            };
+            if order.contains(&dimension) {
+                return Err(format!("Dimension {} listed twice", name));
+            }
+            order.push(dimension);
+        }
+        for dimension in ResultOrder::default().0 {
+            if !order.contains(&dimension) {
+                order.push(dimension);
+            }
+        }
+        Ok(ResultOrder(order))
+    }
+}
+
+/// Default gap in percentage points below which results count as tied
+pub const DEFAULT_TIE_EPSILON: Decimal = Decimal::from_parts(1, 0, 0, false, 4);
+
+/// Prefix printed before amounts in `code`: its symbol for common currencies,
+/// otherwise the code itself (`CHF 1,234.56`)
+pub fn currency_symbol(code: &str) -> String {
+    match code {
+        "USD" => "$".to_string(),
+        "GBP" => "£".to_string(),
+        "EUR" => "€".to_string(),
+        "JPY" | "CNY" => "¥".to_string(),
+        "INR" => "₹".to_string(),
+        other => format!("{} ", other),
+    }
+}
+
+/// Controls how simulation results are rendered
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub locale: Locale,
+    pub precision: Precision,
+    pub format: OutputFormat,
+    pub color: bool, // Color gains and losses with ANSI codes
+    pub summary_only: bool, // Skip the per-result reports
+    pub tie_epsilon: Decimal, // Percentage points within which best/worst results tie
+    pub quiet: bool, // Drop banners, separators and sample trades
+    pub currency: String, // ISO code whose symbol prefixes amounts
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            locale: Locale::default(),
+            precision: Precision::default(),
+            format: OutputFormat::default(),
+            color: false,
+            summary_only: false,
+            tie_epsilon: DEFAULT_TIE_EPSILON,
+            quiet: false,
+            currency: crate::dsl::DEFAULT_CURRENCY.to_string(),
+        }
+    }
+}
+
+impl OutputOptions {
+    pub fn money(&self, value: Decimal) -> String {
+        format!("{}{}", currency_symbol(&self.currency), self.locale.format_number(value, self.precision.money))
+    }
+
+    pub fn percent(&self, value: Decimal) -> String {
+        format!("{}%", self.locale.format_number(value, self.precision.percent))
+    }
+
+    pub fn shares(&self, value: Decimal) -> String {
+        self.locale.format_number(value, self.precision.shares)
+    }
+
+    pub fn ratio(&self, value: Decimal) -> String {
+        self.locale.format_number(value, self.precision.ratio)
+    }
+
+    /// Prints a section heading such as `=== SUMMARY ===` unless quiet
+    pub fn banner(&self, title: &str) {
+        if let Some(banner) = self.banner_text(title) {
+            println!("{}", banner);
+        }
+    }
+
+    /// The section heading `banner` prints, or `None` when quiet
+    pub fn banner_text(&self, title: &str) -> Option<String> {
+        (!self.quiet).then(|| format!("\n=== {} ===", title))
+    }
+
+    /// `text` in green when `value` is a gain and red when it is a loss
+    pub fn gain(&self, text: String, value: Decimal) -> String {
+        let code = if value > Decimal::ZERO {
+            "32"
+        } else if value < Decimal::ZERO {
+            "31"
+        } else {
+            return text;
+        };
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_group_and_separate_decimals() {
+        let value = Decimal::new(-123456789, 2);
+        assert_eq!(Locale::EnUs.format_number(value, 2), "-1,234,567.89");
+        assert_eq!(Locale::DeDe.format_number(value, 2), "-1.234.567,89");
+        assert_eq!(Locale::DeDe.format_number(Decimal::from(999), 0), "999");
+        assert_eq!("de_DE".parse::<Locale>(), Ok(Locale::DeDe));
+        assert!("fr-FR".parse::<Locale>().is_err());
+    }
+
+    #[test]
+    fn precision_applies_per_metric_class() {
+        let precision: Precision = "shares=6, ratio=3".parse().unwrap();
+        assert_eq!(precision, Precision { money: 2, percent: 2, shares: 6, ratio: 3 });
+
+        let options = OutputOptions { precision, ..OutputOptions::default() };
+        let value = Decimal::new(12345678, 6);
+        assert_eq!(options.money(value), "$12.35");
+        assert_eq!(options.percent(value), "12.35%");
+        assert_eq!(options.shares(value), "12.345678");
+        assert_eq!(options.ratio(value), "12.346");
+
+        assert!("shares=11".parse::<Precision>().is_err());
+        assert!("volume=2".parse::<Precision>().is_err());
+        assert!("shares".parse::<Precision>().is_err());
+    }
+
+    #[test]
+    fn color_always_wraps_gains_and_never_leaves_them_plain() {
+        let colored = OutputOptions { color: "always".parse::<ColorMode>().unwrap().enabled(), ..OutputOptions::default() };
+        let plain = OutputOptions { color: "never".parse::<ColorMode>().unwrap().enabled(), ..OutputOptions::default() };
+
+        assert_eq!(colored.gain("5%".to_string(), Decimal::from(5)), "\x1b[32m5%\x1b[0m");
+        assert_eq!(colored.gain("-5%".to_string(), Decimal::from(-5)), "\x1b[31m-5%\x1b[0m");
+        assert_eq!(colored.gain("0%".to_string(), Decimal::ZERO), "0%");
+        assert_eq!(plain.gain("5%".to_string(), Decimal::from(5)), "5%");
+        assert_eq!(plain.gain("-5%".to_string(), Decimal::from(-5)), "-5%");
+        assert!("sometimes".parse::<ColorMode>().is_err());
+    }
+
+    #[test]
+    fn currency_symbols_prefix_money() {
+        assert_eq!(currency_symbol("USD"), "$");
+        assert_eq!(currency_symbol("GBP"), "£");
+        assert_eq!(currency_symbol("EUR"), "€");
+        assert_eq!(currency_symbol("CHF"), "CHF ");
+
+        let value = Decimal::new(123456, 2);
+        let money = |currency: &str| OutputOptions { currency: currency.to_string(), ..OutputOptions::default() }.money(value);
+        assert_eq!(money("GBP"), "£1,234.56");
+        assert_eq!(money("CHF"), "CHF 1,234.56");
+    }
+}
@@ -0,0 +1,7 @@
+pub mod checkpoint;
+pub mod dsl;
+pub mod format;
+pub mod metrics;
+pub mod provider;
+pub mod simulator;
+pub mod yahoo_finance;
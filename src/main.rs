@@ -1,12 +1,13 @@
+use chrono::NaiveDate;
 use clap::Parser;
+use rust_decimal::Decimal;
 use std::path::PathBuf;
 
-mod dsl;
-mod yahoo_finance;
-mod simulator;
-
-use dsl::StockDSL;
-use simulator::Simulator;
+use stock_simulator::checkpoint;
+use stock_simulator::dsl::StockDSL;
+use stock_simulator::format::{ColorMode, Locale, OutputFormat, OutputOptions, Precision, ResultOrder};
+use stock_simulator::simulator::{InitialHoldings, Simulator};
+use stock_simulator::yahoo_finance::YahooFinanceClient;
 
 
 // Define the command-line arguments
@@ -16,6 +17,117 @@ struct Args {
     /// Path to a .stock DSL file. If not specified, the program looks for a 'Test.stock' in the current directory.
     #[clap(short, long, value_name = "FILE")]
     stock_file: Option<PathBuf>,
+
+    /// Reject unknown or incomplete DSL directives instead of skipping them with a warning
+    #[clap(long)]
+    strict: bool,
+
+    /// Number format for printed amounts: en-US (1,234.56) or de-DE (1.234,56)
+    #[clap(long, value_name = "LOCALE", default_value = "en-US")]
+    locale: Locale,
+
+    /// Decimal places per metric class, e.g. "shares=6,ratio=3" (classes: money, percent, shares, ratio)
+    #[clap(long, value_name = "SPEC", default_value = "money=2,percent=2,shares=4,ratio=2")]
+    precision: Precision,
+
+    /// Result layout: text (full report), oneline (one key=value line per result) or broker-csv (trades only)
+    #[clap(long, value_name = "FORMAT", default_value = "text")]
+    format: OutputFormat,
+
+    /// Order results by these dimensions, outermost first, e.g. "frame,pattern" (dimensions: pattern, amount, frame)
+    #[clap(long, value_name = "DIMENSIONS", default_value = "pattern,amount,frame")]
+    order_by: ResultOrder,
+
+    /// Color gains green and losses red: auto (only on a terminal), always or never
+    #[clap(long, value_name = "WHEN", default_value = "auto")]
+    color: ColorMode,
+
+    /// Print only the summary statistics, not each result's report
+    #[clap(long)]
+    summary_only: bool,
+
+    /// Percentage points within which results tie for best or worst in the summary
+    #[clap(long, value_name = "POINTS", default_value = "0.0001")]
+    tie_epsilon: Decimal,
+
+    /// Print only the result metrics: no preamble, banners, separators or sample trades
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Start the simulated weeks on this date (YYYY-MM-DD) instead of today
+    #[clap(long, value_name = "DATE")]
+    start_date: Option<NaiveDate>,
+
+    /// Also value each simulated portfolio as of this date (YYYY-MM-DD)
+    #[clap(long, value_name = "DATE")]
+    as_of: Option<NaiveDate>,
+
+    /// Print the correlation matrix of each tested pattern's constituents
+    #[clap(long)]
+    correlations: bool,
+
+    /// Print where each traded ticker's entry price and return history came from
+    #[clap(long)]
+    explain_data: bool,
+
+    /// Print each pattern's percentage gain side by side for every invest amount
+    #[clap(long)]
+    by_amount: bool,
+
+    /// Rerun each simulation under every FEE_MODEL and tabulate its total fees and net final amount
+    #[clap(long)]
+    compare_fees: bool,
+
+    /// Write each simulation's week-by-week portfolio snapshots to this JSON file
+    #[clap(long, value_name = "FILE")]
+    snapshots: Option<PathBuf>,
+
+    /// Currency every ticker must trade in (e.g. USD); the run stops if any differs
+    #[clap(long, value_name = "CODE")]
+    base_currency: Option<String>,
+
+    /// Keep fetched price data in this file and reuse it for up to an hour on later runs
+    #[clap(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+
+    /// Total time all price fetches may spend backing off between retries, e.g. 30s, 500ms or 2m;
+    /// once spent, the next transient failure stops the run
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    retry_budget: Option<std::time::Duration>,
+
+    /// Save completed simulations to this file and resume from it on the next run
+    #[clap(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+
+    /// Fetch price history from this date (YYYY-MM-DD) instead of the trailing year
+    #[clap(long, value_name = "DATE")]
+    history_start: Option<NaiveDate>,
+
+    /// End of the fetched price history (YYYY-MM-DD); defaults to today with --history-start
+    #[clap(long, value_name = "DATE", requires = "history_start")]
+    history_end: Option<NaiveDate>,
+
+    /// Continue from an existing portfolio in this JSON file ({"cash": ..., "shares": {"TICKER": ...}})
+    #[clap(long, value_name = "FILE")]
+    initial_holdings: Option<PathBuf>,
+
+    /// Run only this pattern instead of the file's TEST list (repeat for several)
+    #[clap(long, value_name = "PATTERN")]
+    only: Vec<String>,
+}
+
+/// Parses a whole number of milliseconds, seconds or minutes, e.g. `30s`
+fn parse_duration(text: &str) -> Result<std::time::Duration, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (count, unit) = text.split_at(split);
+    let count: u64 = count.parse().map_err(|_| format!("expected a duration like 30s, got '{}'", text))?;
+    match unit {
+        "ms" => Ok(std::time::Duration::from_millis(count)),
+        "s" => Ok(std::time::Duration::from_secs(count)),
+        "m" => Ok(std::time::Duration::from_secs(count * 60)),
+        _ => Err(format!("unknown duration unit '{}' (use ms, s or m)", unit)),
+    }
 }
 
 #[tokio::main]
@@ -24,7 +136,12 @@ async fn main() {
     let stock_file_path = args.stock_file.unwrap_or_else(|| PathBuf::from("Test.stock"));
 
     // Parse the DSL file
-    let dsl = match StockDSL::parse_file(&stock_file_path) {
+    let parsed = if args.strict {
+        StockDSL::parse_file_strict(&stock_file_path)
+    } else {
+        StockDSL::parse_file(&stock_file_path)
+    };
+    let mut dsl = match parsed {
         Ok(d) => d,
         Err(e) => {
             eprintln!("Error parsing stock file {:?}: {}", stock_file_path, e);
@@ -32,20 +149,135 @@ async fn main() {
         }
     };
 
-    println!("Stock Simulator - Processing {:?}\n", stock_file_path);
-    println!("Investment amounts: {:?}", dsl.invest_amounts);
-    println!("Time frames: {:?}", dsl.time_frames);
-    println!("Investments: {:?}", dsl.investments.keys().collect::<Vec<_>>());
-    println!("Patterns: {:?}", dsl.patterns.keys().collect::<Vec<_>>());
-    println!("Tests to run: {:?}\n", dsl.tests);
+    for warning in &dsl.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if !args.only.is_empty()
+        && let Err(unknown) = dsl.restrict_tests(&args.only)
+    {
+        eprintln!("Error: --only {} does not match any PATTERN in {:?}", unknown, stock_file_path);
+        return;
+    }
+
+    if let Err(e) = dsl.validate() {
+        eprintln!("Error in stock file {:?}:\n{}", stock_file_path, e);
+        return;
+    }
+
+    // Keep exported CSV and quiet output free of progress output
+    let verbose = args.format != OutputFormat::BrokerCsv && !args.quiet;
+    if verbose {
+        println!("Stock Simulator - Processing {:?}\n", stock_file_path);
+        println!("Investment amounts: {:?}", dsl.invest_amounts);
+        println!("Time frames: {:?}", dsl.time_frames);
+        println!("Investments: {:?}", dsl.investments.keys().collect::<Vec<_>>());
+        println!("Patterns: {:?}", dsl.patterns.keys().collect::<Vec<_>>());
+        println!("Tests to run: {:?}\n", dsl.tests);
+    }
 
     // Create simulator and run simulations
-    let mut simulator = Simulator::new();
+    let mut client = YahooFinanceClient::new()
+        .with_suffix_fallbacks(dsl.ticker_suffixes.clone())
+        .with_price_check(dsl.price_check);
+    if let Some(start) = args.history_start {
+        client = client.with_history_dates(start, args.history_end);
+    }
+    if let Some(path) = args.cache.clone() {
+        client = client.with_cache_file(path);
+    }
+    if let Some(budget) = args.retry_budget {
+        client = client.with_retry_budget(budget);
+    }
+    let mut simulator = Simulator::with_provider(client);
+    if let Some(currency) = args.base_currency.clone() {
+        simulator = simulator.with_base_currency(currency);
+    }
+    if let Some(path) = &args.initial_holdings {
+        match InitialHoldings::load(path) {
+            Ok(holdings) => simulator = simulator.with_initial_holdings(holdings),
+            Err(e) => {
+                eprintln!("Error loading initial holdings: {}", e);
+                return;
+            }
+        }
+    }
+    if let Some(path) = args.checkpoint.clone() {
+        match std::fs::read(&stock_file_path) {
+            Ok(mut content) => {
+                // Results depend on included files, seeded prices, the starting
+                // portfolio and fetched history too
+                for path in dsl.included_files.iter().chain(&dsl.prices_file).chain(&args.initial_holdings) {
+                    content.extend(std::fs::read(path).unwrap_or_default());
+                }
+                content.extend(format!("\nhistory {:?} {:?}", args.history_start, args.history_end).into_bytes());
+                simulator = simulator.with_checkpoint(path, checkpoint::content_hash(&content));
+            }
+            Err(e) => {
+                eprintln!("Error reading stock file {:?}: {}", stock_file_path, e);
+                return;
+            }
+        }
+    }
+    if let Some(date) = args.start_date {
+        simulator = simulator.with_start_date(date);
+    }
+    if let Some(date) = args.as_of {
+        simulator = simulator.with_as_of(date);
+    }
     
-    println!("Fetching stock data from Yahoo Finance...");
+    if verbose {
+        println!("Fetching stock data from Yahoo Finance...");
+    }
     match simulator.run_simulations(&dsl).await {
-        Ok(results) => {
-            Simulator::print_results(&results);
+        Ok(mut results) => {
+            Simulator::order_results(&mut results, &args.order_by);
+            let options = OutputOptions {
+                locale: args.locale,
+                precision: args.precision,
+                format: args.format,
+                color: args.color.enabled(),
+                summary_only: args.summary_only,
+                tie_epsilon: args.tie_epsilon,
+                quiet: args.quiet,
+                currency: dsl.currency.clone(),
+            };
+            Simulator::print_results(&results, &options);
+            if args.format == OutputFormat::Text && !args.quiet {
+                println!("\nRun fingerprint: {}", Simulator::fingerprint(&results));
+            }
+
+            if args.by_amount {
+                Simulator::print_amount_table(&Simulator::group_by_amount(&results), &options);
+            }
+
+            if let Some(path) = &args.snapshots {
+                match Simulator::write_snapshots(&results, path) {
+                    Ok(()) => eprintln!("Wrote weekly snapshots to {:?}", path),
+                    Err(e) => eprintln!("Error writing snapshots to {:?}: {}", path, e),
+                }
+            }
+
+            if args.compare_fees {
+                match simulator.compare_fees(&dsl).await {
+                    Ok(comparisons) => Simulator::print_fee_comparison(&comparisons, &options),
+                    Err(e) => eprintln!("Error comparing fee models: {}", e),
+                }
+            }
+
+            if args.explain_data {
+                match simulator.price_provenance(&dsl).await {
+                    Ok(provenance) => Simulator::print_provenance(&provenance, &options),
+                    Err(e) => eprintln!("Error explaining price data: {}", e),
+                }
+            }
+
+            if args.correlations {
+                match simulator.correlation_matrices(&dsl).await {
+                    Ok(matrices) => Simulator::print_correlations(&matrices, &options),
+                    Err(e) => eprintln!("Error computing correlations: {}", e),
+                }
+            }
         }
         Err(e) => {
             eprintln!("Error running simulations: {}", e);
@@ -0,0 +1,568 @@
+use chrono::{Datelike, Days, NaiveDate};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollingReturns {
+    pub window_weeks: u32,
+    pub min: Decimal,
+    pub median: Decimal,
+    pub max: Decimal,
+}
+
+/// Distribution of every overlapping `window`-week return along an equity curve.
+/// Returns `None` when the curve is too short to hold a single window.
+pub fn rolling_returns(equity_curve: &[Decimal], window_weeks: u32) -> Option<RollingReturns> {
+    let window = window_weeks as usize;
+    if window == 0 || equity_curve.len() <= window {
+        return None;
+    }
+
+    let mut returns: Vec<Decimal> = equity_curve
+        .windows(window + 1)
+        .filter(|w| w[0] > Decimal::ZERO)
+        .map(|w| (w[window] - w[0]) / w[0])
+        .collect();
+
+    if returns.is_empty() {
+        return None;
+    }
+
+    returns.sort();
+    Some(RollingReturns {
+        window_weeks,
+        min: returns[0],
+        median: median(&returns),
+        max: returns[returns.len() - 1],
+    })
+}
+
+/// Return of each calendar year touched by a weekly equity curve starting on
+/// `start`, from the last value of the previous year (or the start) to the
+/// year's last value. Partial first and last years cover only their weeks.
+pub fn calendar_year_returns(start: NaiveDate, equity_curve: &[Decimal]) -> Vec<(i32, Decimal)> {
+    let Some(&first) = equity_curve.first() else {
+        return Vec::new();
+    };
+
+    let mut years: Vec<(i32, Decimal)> = Vec::new();
+    let mut opening = first;
+    let mut closing = first;
+    let mut year = start.year();
+    for (week, value) in equity_curve.iter().enumerate().skip(1) {
+        let week_year = (start + Days::new(7 * week as u64)).year();
+        if week_year != year {
+            if opening > Decimal::ZERO {
+                years.push((year, (closing - opening) / opening));
+            }
+            opening = closing;
+            year = week_year;
+        }
+        closing = *value;
+    }
+    if equity_curve.len() > 1 && opening > Decimal::ZERO {
+        years.push((year, (closing - opening) / opening));
+    }
+    years
+}
+
+/// Median of an already sorted, non-empty slice
+pub fn median(sorted: &[Decimal]) -> Decimal {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / Decimal::TWO
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Time-weighted return of an equity curve with external cash flows, where
+/// `cash_flows[i]` entered (positive) or left (negative) the portfolio at point
+/// `i` after that week's growth. Each week's return excludes its flow and the
+/// weeks are chained, so the timing and size of flows don't affect the result.
+/// Weeks starting from an empty portfolio are skipped.
+pub fn time_weighted_return(equity_curve: &[Decimal], cash_flows: &[Decimal]) -> Decimal {
+    let growth: Decimal = equity_curve.windows(2)
+        .zip(cash_flows.iter().skip(1))
+        .filter(|(w, _)| w[0] > Decimal::ZERO)
+        .map(|(w, flow)| (w[1] - flow) / w[0])
+        .product();
+    growth - Decimal::ONE
+}
+
+/// Largest peak-to-trough decline along an equity curve, as a fraction of the peak
+pub fn max_drawdown(equity_curve: &[Decimal]) -> Decimal {
+    let mut peak = Decimal::ZERO;
+    let mut drawdown = Decimal::ZERO;
+    for value in equity_curve {
+        peak = peak.max(*value);
+        if peak > Decimal::ZERO {
+            drawdown = drawdown.max((peak - value) / peak);
+        }
+    }
+    drawdown
+}
+
+/// Ulcer index of an equity curve: the root mean square of each point's
+/// percentage drawdown from the running peak, so deep and long drawdowns
+/// both raise it. In percentage points; zero for a curve that never falls.
+pub fn ulcer_index(equity_curve: &[Decimal]) -> Decimal {
+    let mut peak = Decimal::ZERO;
+    let mut squares = Vec::with_capacity(equity_curve.len());
+    for value in equity_curve {
+        peak = peak.max(*value);
+        if peak > Decimal::ZERO {
+            let drawdown = ((peak - value) / peak * Decimal::from(100)).to_f64().unwrap_or(0.0);
+            squares.push(drawdown * drawdown);
+        }
+    }
+    if squares.is_empty() {
+        return Decimal::ZERO;
+    }
+
+    let mean = squares.iter().sum::<f64>() / squares.len() as f64;
+    Decimal::try_from(mean.sqrt()).unwrap_or(Decimal::ZERO)
+}
+
+/// Loss not exceeded in a week with probability `confidence`, as a fraction:
+/// the negated empirical quantile of `weekly_returns`, floored at zero. `None`
+/// without any returns.
+pub fn historical_var(weekly_returns: &[Decimal], confidence: Decimal) -> Option<Decimal> {
+    if weekly_returns.is_empty() {
+        return None;
+    }
+    let mut sorted = weekly_returns.to_vec();
+    sorted.sort();
+
+    // The k-th worst week, where k weeks in n make up the tail
+    let tail = ((Decimal::ONE - confidence) * Decimal::from(sorted.len())).ceil();
+    let index = tail.to_usize().unwrap_or(1).clamp(1, sorted.len()) - 1;
+    Some((-sorted[index]).max(Decimal::ZERO))
+}
+
+/// Loss not exceeded in a week with probability `confidence`, as a fraction,
+/// assuming normally distributed weekly returns with their sample mean and
+/// standard deviation; floored at zero. `None` with fewer than two returns.
+pub fn parametric_var(weekly_returns: &[Decimal], confidence: Decimal) -> Option<Decimal> {
+    if weekly_returns.len() < 2 {
+        return None;
+    }
+
+    let returns: Vec<f64> = weekly_returns.iter().filter_map(|r| r.to_f64()).collect();
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let std_dev = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    let z = normal_quantile(confidence.to_f64()?);
+
+    Decimal::try_from((z * std_dev - mean).max(0.0)).ok()
+}
+
+/// Inverse of the standard normal CDF, by Acklam's rational approximation
+/// (relative error below 1.2e-9). `p` must lie strictly between 0 and 1.
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2,
+        1.38357751867269e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2,
+        6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838,
+        -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996,
+        3.754408661907416];
+    const P_LOW: f64 = 0.02425;
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Longest runs of consecutive rising and falling weeks along an equity curve,
+/// as (winning, losing); an unchanged week ends either run
+pub fn streaks(equity_curve: &[Decimal]) -> (u32, u32) {
+    let (mut win, mut loss) = (0, 0);
+    let (mut max_win, mut max_loss) = (0, 0);
+    for pair in equity_curve.windows(2) {
+        if pair[1] > pair[0] {
+            win += 1;
+            loss = 0;
+        } else if pair[1] < pair[0] {
+            loss += 1;
+            win = 0;
+        } else {
+            win = 0;
+            loss = 0;
+        }
+        max_win = max_win.max(win);
+        max_loss = max_loss.max(loss);
+    }
+    (max_win, max_loss)
+}
+
+/// Annualized performance of a portfolio relative to its benchmark
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveRisk {
+    pub active_return: Decimal,  // Mean weekly excess return × 52
+    pub tracking_error: Decimal, // Standard deviation of weekly excess returns × √52
+    pub information_ratio: Decimal,
+}
+
+/// A portfolio with the benchmark's return subtracted every week, isolating the
+/// strategy's own path from market moves
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HedgedPerformance {
+    pub curve: Vec<Decimal>,     // Growth of 1 compounded from weekly excess returns
+    pub total_return: Decimal,
+    pub volatility: Decimal,     // Annualized
+    pub sharpe: Option<Decimal>, // Annualized mean over volatility, zero risk-free rate
+    pub max_drawdown: Decimal,
+}
+
+/// Market-neutral performance of `portfolio` against `benchmark` weekly returns,
+/// aligned on their most recent overlapping observations. `None` with fewer
+/// than two observations.
+pub fn hedged_performance(portfolio: &[Decimal], benchmark: &[Decimal]) -> Option<HedgedPerformance> {
+    let n = portfolio.len().min(benchmark.len());
+    if n < 2 {
+        return None;
+    }
+
+    let excess: Vec<Decimal> = portfolio[portfolio.len() - n..].iter()
+        .zip(&benchmark[benchmark.len() - n..])
+        .map(|(p, b)| p - b)
+        .collect();
+
+    let mut curve = vec![Decimal::ONE];
+    for r in &excess {
+        let last = curve[curve.len() - 1];
+        curve.push((last * (Decimal::ONE + r)).max(Decimal::ZERO));
+    }
+
+    let volatility = annualized_volatility(&excess)?;
+    let mean = excess.iter().sum::<Decimal>() / Decimal::from(n);
+    let sharpe = if volatility > Decimal::ZERO {
+        Some(mean * Decimal::from(52) / volatility)
+    } else {
+        None
+    };
+
+    Some(HedgedPerformance {
+        total_return: curve[curve.len() - 1] - Decimal::ONE,
+        volatility,
+        sharpe,
+        max_drawdown: max_drawdown(&curve),
+        curve,
+    })
+}
+
+/// Active return, tracking error and information ratio of `portfolio` against
+/// `benchmark` weekly returns, aligned on their most recent overlapping
+/// observations. `None` with fewer than two observations or when the portfolio
+/// tracks the benchmark exactly.
+pub fn active_risk(portfolio: &[Decimal], benchmark: &[Decimal]) -> Option<ActiveRisk> {
+    let n = portfolio.len().min(benchmark.len());
+    if n < 2 {
+        return None;
+    }
+
+    let excess: Vec<f64> = portfolio[portfolio.len() - n..].iter()
+        .zip(&benchmark[benchmark.len() - n..])
+        .filter_map(|(p, b)| (p - b).to_f64())
+        .collect();
+    let count = excess.len() as f64;
+    let mean = excess.iter().sum::<f64>() / count;
+    let variance = excess.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (count - 1.0);
+    if variance <= 0.0 {
+        return None;
+    }
+
+    let active_return = mean * 52.0;
+    let tracking_error = variance.sqrt() * 52f64.sqrt();
+    Some(ActiveRisk {
+        active_return: Decimal::try_from(active_return).ok()?,
+        tracking_error: Decimal::try_from(tracking_error).ok()?,
+        information_ratio: Decimal::try_from(active_return / tracking_error).ok()?,
+    })
+}
+
+/// Pearson correlation of two return series, aligned on their most recent
+/// overlapping observations. `None` when there is too little data or either
+/// series is flat.
+pub fn correlation(a: &[Decimal], b: &[Decimal]) -> Option<Decimal> {
+    let n = a.len().min(b.len());
+    if n < 2 {
+        return None;
+    }
+
+    let a: Vec<f64> = a[a.len() - n..].iter().filter_map(|r| r.to_f64()).collect();
+    let b: Vec<f64> = b[b.len() - n..].iter().filter_map(|r| r.to_f64()).collect();
+    if a.len() != n || b.len() != n {
+        return None;
+    }
+
+    let mean_a = a.iter().sum::<f64>() / n as f64;
+    let mean_b = b.iter().sum::<f64>() / n as f64;
+
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(&b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return None;
+    }
+
+    Decimal::try_from(covariance / (variance_a.sqrt() * variance_b.sqrt())).ok()
+}
+
+/// Averages several return series observation by observation, aligned on their
+/// most recent overlapping observations
+pub fn average_returns(series: &[Vec<Decimal>]) -> Vec<Decimal> {
+    weighted_returns(series, &vec![Decimal::ONE; series.len()])
+}
+
+/// Weighted average of several return series (a blend rebalanced to fixed
+/// weights every week), aligned on their most recent overlapping observations
+pub fn weighted_returns(series: &[Vec<Decimal>], weights: &[Decimal]) -> Vec<Decimal> {
+    let n = series.iter().map(|returns| returns.len()).min().unwrap_or(0);
+    let total_weight: Decimal = weights.iter().sum();
+    if n == 0 || total_weight <= Decimal::ZERO {
+        return Vec::new();
+    }
+
+    (0..n).map(|i| {
+        series.iter().zip(weights)
+            .map(|(returns, weight)| returns[returns.len() - n + i] * weight)
+            .sum::<Decimal>() / total_weight
+    }).collect()
+}
+
+/// Exponential weights for `n` observations, oldest first: each observation
+/// counts `lambda` times as much as the next, normalized to sum to one
+fn ewma_weights(n: usize, lambda: f64) -> Vec<f64> {
+    let raw: Vec<f64> = (0..n).map(|i| lambda.powi((n - 1 - i) as i32)).collect();
+    let total: f64 = raw.iter().sum();
+    raw.iter().map(|w| w / total).collect()
+}
+
+/// Exponentially weighted mean of a return series, most recent observation
+/// weighted highest. `None` for an empty series.
+pub fn ewma_mean(returns: &[Decimal], lambda: Decimal) -> Option<Decimal> {
+    let returns: Vec<f64> = returns.iter().filter_map(|r| r.to_f64()).collect();
+    if returns.is_empty() {
+        return None;
+    }
+
+    let weights = ewma_weights(returns.len(), lambda.to_f64()?);
+    Decimal::try_from(returns.iter().zip(&weights).map(|(r, w)| r * w).sum::<f64>()).ok()
+}
+
+/// Annualized volatility of weekly returns from their exponentially weighted
+/// variance about the weighted mean. `None` with fewer than two observations.
+pub fn ewma_volatility(weekly_returns: &[Decimal], lambda: Decimal) -> Option<Decimal> {
+    if weekly_returns.len() < 2 {
+        return None;
+    }
+
+    let returns: Vec<f64> = weekly_returns.iter().filter_map(|r| r.to_f64()).collect();
+    let weights = ewma_weights(returns.len(), lambda.to_f64()?);
+    let mean: f64 = returns.iter().zip(&weights).map(|(r, w)| r * w).sum();
+    let variance: f64 = returns.iter().zip(&weights).map(|(r, w)| w * (r - mean).powi(2)).sum();
+
+    Decimal::try_from(variance.sqrt() * 52f64.sqrt()).ok()
+}
+
+/// Annualized volatility (sample standard deviation × √52) of weekly returns.
+/// `None` with fewer than two observations.
+pub fn annualized_volatility(weekly_returns: &[Decimal]) -> Option<Decimal> {
+    if weekly_returns.len() < 2 {
+        return None;
+    }
+
+    let returns: Vec<f64> = weekly_returns.iter().filter_map(|r| r.to_f64()).collect();
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+
+    Decimal::try_from(variance.sqrt() * 52f64.sqrt()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(values: &[i64]) -> Vec<Decimal> {
+        values.iter().map(|v| Decimal::from(*v)).collect()
+    }
+
+    #[test]
+    fn rolling_returns_spans_every_overlapping_window() {
+        // Two-week returns: 100→120 +20%, 110→99 -10%, 120→132 +10%
+        let rolling = rolling_returns(&curve(&[100, 110, 120, 99, 132]), 2).unwrap();
+        assert_eq!(rolling.window_weeks, 2);
+        assert_eq!(rolling.min, Decimal::new(-10, 2));
+        assert_eq!(rolling.median, Decimal::new(10, 2));
+        assert_eq!(rolling.max, Decimal::new(20, 2));
+    }
+
+    #[test]
+    fn rolling_returns_needs_a_full_window() {
+        assert!(rolling_returns(&curve(&[100, 110]), 2).is_none());
+        assert!(rolling_returns(&curve(&[100, 110, 120]), 0).is_none());
+    }
+
+    fn returns(values: &[i64]) -> Vec<Decimal> {
+        values.iter().map(|v| Decimal::new(*v, 2)).collect()
+    }
+
+    #[test]
+    fn correlation_of_identical_and_mirrored_series() {
+        let a = returns(&[1, -2, 3, 0, 2]);
+        let mirrored: Vec<Decimal> = a.iter().map(|r| -r).collect();
+        let close_to = |value: Option<Decimal>, expected: i64| {
+            (value.unwrap() - Decimal::from(expected)).abs() < Decimal::new(1, 9)
+        };
+        assert!(close_to(correlation(&a, &a), 1));
+        assert!(close_to(correlation(&a, &mirrored), -1));
+        assert!(correlation(&a, &returns(&[1, 1, 1, 1, 1])).is_none());
+    }
+
+    #[test]
+    fn active_risk_matches_a_hand_computed_information_ratio() {
+        // Excess returns 1%, -1%, 2%: mean 2/3%, sample deviation √(7/3)%;
+        // the leading portfolio week has no benchmark week and is dropped
+        let risk = active_risk(&returns(&[50, 2, 0, 3]), &returns(&[1, 1, 1])).unwrap();
+        assert_eq!(risk.active_return.round_dp(6), Decimal::new(346667, 6));
+        assert_eq!(risk.tracking_error.round_dp(6), Decimal::new(110151, 6));
+        assert_eq!(risk.information_ratio.round_dp(4), Decimal::new(31472, 4));
+
+        assert!(active_risk(&returns(&[1, 2, 3]), &returns(&[1, 2, 3])).is_none());
+        assert!(active_risk(&returns(&[1]), &returns(&[2])).is_none());
+    }
+
+    #[test]
+    fn ewma_estimates_react_faster_to_recent_weeks() {
+        // Twenty flat weeks, then a run of 2% weeks
+        let mut recent_rally = vec![0; 20];
+        recent_rally.extend([2, 2, 2, 2]);
+        let rally = returns(&recent_rally);
+        let lambda = Decimal::new(8, 1);
+
+        let simple_mean = rally.iter().sum::<Decimal>() / Decimal::from(rally.len());
+        let ewma = ewma_mean(&rally, lambda).unwrap();
+        assert!(ewma > simple_mean * Decimal::from(2), "{} vs {}", ewma, simple_mean);
+        assert_eq!(ewma_mean(&returns(&[3, 3, 3]), lambda).unwrap().round_dp(10), Decimal::new(3, 2));
+
+        // A calm stretch following a turbulent one
+        let mut calming = vec![5, -5, 5, -5, 5, -5];
+        calming.extend([0; 20]);
+        let calming = returns(&calming);
+        assert!(ewma_volatility(&calming, lambda).unwrap() < annualized_volatility(&calming).unwrap());
+    }
+
+    #[test]
+    fn streaks_count_the_longest_up_and_down_runs() {
+        // Up 2, down 1, up 3, flat, down 2
+        assert_eq!(streaks(&curve(&[100, 101, 102, 101, 102, 103, 104, 104, 103, 102])), (3, 2));
+        // A flat week breaks a run rather than extending it
+        assert_eq!(streaks(&curve(&[100, 101, 101, 102, 103])), (2, 0));
+        assert_eq!(streaks(&curve(&[100])), (0, 0));
+    }
+
+    #[test]
+    fn hedging_out_the_benchmark_removes_its_volatility() {
+        let market = returns(&[5, -5, 5, -5, 5, -5]);
+        let portfolio = returns(&[6, -4, 7, -5, 6, -3]);
+
+        let hedged = hedged_performance(&portfolio, &market).unwrap();
+        let raw = annualized_volatility(&portfolio).unwrap();
+        assert!(hedged.volatility * Decimal::from(5) < raw, "{} vs {}", hedged.volatility, raw);
+        // Excess returns 1, 1, 2, 0, 1, 2% compound without a losing week
+        assert_eq!(hedged.curve.len(), 7);
+        assert_eq!(hedged.total_return, Decimal::new(719251604, 10));
+        assert_eq!(hedged.max_drawdown, Decimal::ZERO);
+        assert!(hedged.sharpe.unwrap() > Decimal::ZERO);
+    }
+
+    #[test]
+    fn calendar_year_returns_split_two_years_at_new_year() {
+        // Weekly from Monday 2023-01-02: week 51 is 2023-12-25, week 52 is 2024-01-01
+        let values: Vec<i64> = (0..=104)
+            .map(|week| match week {
+                0..=25 => 100,
+                26..=51 => 110,
+                _ => 121,
+            })
+            .collect();
+        let start = NaiveDate::from_ymd_opt(2023, 1, 2).unwrap();
+
+        assert_eq!(
+            calendar_year_returns(start, &curve(&values)),
+            [(2023, Decimal::new(1, 1)), (2024, Decimal::new(1, 1))],
+        );
+        assert!(calendar_year_returns(start, &curve(&[100])).is_empty());
+    }
+
+    #[test]
+    fn time_weighted_return_chains_around_a_contribution() {
+        // +10%, then 100 added, then +10% on the larger portfolio
+        let equity = curve(&[100, 210, 231]);
+        let flows = curve(&[0, 100, 0]);
+
+        let twrr = time_weighted_return(&equity, &flows);
+        assert_eq!(twrr, Decimal::new(21, 2));
+        let naive = (equity[2] - equity[0]) / equity[0];
+        assert_eq!(naive, Decimal::new(131, 2));
+        // Without flows it is the plain total return
+        assert_eq!(time_weighted_return(&curve(&[100, 110, 121]), &curve(&[0, 0, 0])), Decimal::new(21, 2));
+    }
+
+    #[test]
+    fn ulcer_index_is_the_rms_of_percentage_drawdowns() {
+        // Drawdowns 0, 50, 0, 0: sqrt(2500 / 4)
+        assert_eq!(ulcer_index(&curve(&[100, 50, 100, 100])), Decimal::from(25));
+        // Drawdowns 0, 20, 10, 0, 0: sqrt(500 / 5)
+        assert_eq!(ulcer_index(&curve(&[100, 80, 90, 100, 120])), Decimal::from(10));
+        assert_eq!(ulcer_index(&curve(&[100, 110, 120])), Decimal::ZERO);
+        assert_eq!(ulcer_index(&[]), Decimal::ZERO);
+    }
+
+    #[test]
+    fn historical_var_takes_the_tail_quantile() {
+        // Weekly returns of -10% through +9%, one each
+        let weekly = returns(&(-10..10).collect::<Vec<i64>>());
+        // 5% of 20 weeks is the single worst; 10% reaches the second worst
+        assert_eq!(historical_var(&weekly, Decimal::new(95, 2)), Some(Decimal::new(10, 2)));
+        assert_eq!(historical_var(&weekly, Decimal::new(90, 2)), Some(Decimal::new(9, 2)));
+        // 12% of 20 weeks rounds up to the third worst
+        assert_eq!(historical_var(&weekly, Decimal::new(88, 2)), Some(Decimal::new(8, 2)));
+        assert_eq!(historical_var(&returns(&[1, 2, 3]), Decimal::new(95, 2)), Some(Decimal::ZERO));
+        assert_eq!(historical_var(&[], Decimal::new(95, 2)), None);
+    }
+
+    #[test]
+    fn parametric_var_scales_the_standard_deviation() {
+        assert!((normal_quantile(0.95) - 1.644_853_627).abs() < 1e-8);
+        assert!((normal_quantile(0.99) - 2.326_347_874).abs() < 1e-8);
+
+        // Mean zero, sample standard deviation sqrt(0.0004 / 3)
+        let var = parametric_var(&returns(&[1, -1, 1, -1]), Decimal::new(95, 2)).unwrap();
+        let expected = 1.644_853_627 * (0.0004f64 / 3.0).sqrt();
+        assert!((var.to_f64().unwrap() - expected).abs() < 1e-9, "{}", var);
+        assert_eq!(parametric_var(&returns(&[1]), Decimal::new(95, 2)), None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::dsl::EndAlign;
+use crate::yahoo_finance::{StockData, YahooFinanceClient};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type ProviderResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+pub type ProviderFuture<'a, T> = Pin<Box<dyn Future<Output = ProviderResult<T>> + Send + 'a>>;
+
+/// Source of stock data for the simulator
+pub trait StockDataProvider: Send {
+    /// Returns data for `symbol`, fetching it if it is not already cached
+    fn get_stock_data<'a>(&'a mut self, symbol: &'a str) -> ProviderFuture<'a, Arc<StockData>>;
+
+    /// Annualized return of a symbol that has already been fetched, ending at
+    /// the close `align` picks
+    fn calculate_annual_return(&self, symbol: &str, align: EndAlign) -> ProviderResult<Decimal>;
+
+    /// Pins `symbol` to a fixed current price so it is never fetched
+    fn seed_price(&mut self, symbol: &str, price: Decimal);
+
+    /// Loads every symbol into the cache up front, failing on the first symbol
+    /// in order that can't be loaded. Fetches one at a time unless overridden.
+    fn prefetch<'a>(&'a mut self, symbols: &'a [String]) -> ProviderFuture<'a, ()> {
+        Box::pin(async move {
+            for symbol in symbols {
+                self.get_stock_data(symbol).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl StockDataProvider for YahooFinanceClient {
+    fn get_stock_data<'a>(&'a mut self, symbol: &'a str) -> ProviderFuture<'a, Arc<StockData>> {
+        Box::pin(YahooFinanceClient::get_stock_data(self, symbol))
+    }
+
+    fn calculate_annual_return(&self, symbol: &str, align: EndAlign) -> ProviderResult<Decimal> {
+        YahooFinanceClient::calculate_annual_return(self, symbol, align)
+    }
+
+    fn seed_price(&mut self, symbol: &str, price: Decimal) {
+        YahooFinanceClient::seed_price(self, symbol, price)
+    }
+
+    fn prefetch<'a>(&'a mut self, symbols: &'a [String]) -> ProviderFuture<'a, ()> {
+        Box::pin(YahooFinanceClient::prefetch(self, symbols))
+    }
+}
+
+/// Serves fixed current prices with no price history, so every symbol has a
+/// zero annual return. Useful for running simulations offline.
+#[derive(Default)]
+pub struct FixedPriceProvider {
+    data: HashMap<String, Arc<StockData>>,
+}
+
+impl FixedPriceProvider {
+    pub fn new(prices: HashMap<String, Decimal>) -> Self {
+        let mut provider = FixedPriceProvider::default();
+        for (symbol, price) in prices {
+            provider.seed_price(&symbol, price);
+        }
+        provider
+    }
+}
+
+impl StockDataProvider for FixedPriceProvider {
+    fn get_stock_data<'a>(&'a mut self, symbol: &'a str) -> ProviderFuture<'a, Arc<StockData>> {
+        let result = self.data.get(symbol)
+            .cloned()
+            .ok_or_else(|| format!("No fixed price for symbol: {}", symbol).into());
+        Box::pin(async move { result })
+    }
+
+    fn calculate_annual_return(&self, symbol: &str, align: EndAlign) -> ProviderResult<Decimal> {
+        self.data.get(symbol)
+            .ok_or(format!("No fixed price for symbol: {}", symbol))?
+            .annual_return(align)
+    }
+
+    fn seed_price(&mut self, symbol: &str, price: Decimal) {
+        self.data.insert(symbol.to_string(), Arc::new(StockData::fixed(symbol, price)));
+    }
+}
+
+/// Providers for tests that need real price histories without the network
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::yahoo_finance::HistoricalPrice;
+    use chrono::{Duration, TimeZone, Utc};
+
+    /// Serves prepared `StockData`, ignoring seeded prices
+    #[derive(Default)]
+    pub(crate) struct HistoryProvider {
+        data: HashMap<String, Arc<StockData>>,
+    }
+
+    impl HistoryProvider {
+        pub(crate) fn new(series: impl IntoIterator<Item = StockData>) -> Self {
+            HistoryProvider {
+                data: series.into_iter().map(|data| (data.symbol.clone(), Arc::new(data))).collect(),
+            }
+        }
+    }
+
+    /// Weekly closes from Monday 2024-01-01; the current price is the last close
+    pub(crate) fn series(symbol: &str, closes: &[i64]) -> StockData {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut data = StockData::fixed(symbol, Decimal::from(*closes.last().unwrap()));
+        data.historical_prices = closes.iter().enumerate()
+            .map(|(week, &close)| {
+                let close = Decimal::from(close);
+                HistoricalPrice {
+                    date: start + Duration::weeks(week as i64),
+                    open: close,
+                    high: close,
+                    low: close,
+                    close,
+                    volume: 0,
+                }
+            })
+            .collect();
+        data
+    }
+
+    impl StockDataProvider for HistoryProvider {
+        fn get_stock_data<'a>(&'a mut self, symbol: &'a str) -> ProviderFuture<'a, Arc<StockData>> {
+            let result = self.data.get(symbol)
+                .cloned()
+                .ok_or_else(|| format!("No test series for symbol: {}", symbol).into());
+            Box::pin(async move { result })
+        }
+
+        fn calculate_annual_return(&self, symbol: &str, align: EndAlign) -> ProviderResult<Decimal> {
+            self.data.get(symbol)
+                .ok_or(format!("No test series for symbol: {}", symbol))?
+                .annual_return(align)
+        }
+
+        fn seed_price(&mut self, _symbol: &str, _price: Decimal) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fixed_prices_serve_seeded_data_with_no_return() {
+        let mut provider = FixedPriceProvider::new(HashMap::from([("AAPL".to_string(), Decimal::from(200))]));
+        let data = provider.get_stock_data("AAPL").await.unwrap();
+        assert!(data.fixed);
+        assert_eq!(data.current_price, Decimal::from(200));
+        assert_eq!(provider.calculate_annual_return("AAPL", EndAlign::Partial).unwrap(), Decimal::ZERO);
+        assert!(provider.get_stock_data("MSFT").await.is_err());
+    }
+}
@@ -1,47 +1,517 @@
-use crate::dsl::{StockDSL, TimeFrame, TimeUnit};
+use crate::checkpoint::{self, Checkpoint};
+use crate::dsl::{InitialDeploy, ReturnEndpoint, StockDSL, TimeFrame, TimeUnit, VarMethod, Withdrawal};
+use crate::format::{self, Dimension, OutputFormat, OutputOptions, ResultOrder};
+use crate::metrics::{self, ActiveRisk, HedgedPerformance, RollingReturns};
+use crate::provider::{FixedPriceProvider, StockDataProvider};
 use crate::yahoo_finance::YahooFinanceClient;
+use chrono::{Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::ToPrimitive;
 use std::collections::HashMap;
 use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
+/// Longest calendar gap between daily bars before it is reported; a holiday
+/// weekend spans four days, so anything past a week is missing data
+const MAX_DATA_GAP_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimulationResult {
     pub pattern_name: String,
+    pub description: Option<String>, // The pattern's DESCRIBE text
     pub initial_amount: Decimal,
     pub time_frame: TimeFrame,
+    pub currency: String, // CURRENCY code of every amount
     pub final_amount: Decimal,
     pub total_gain: Decimal,
     pub percentage_gain: Decimal,
     pub trades: Vec<Trade>,
+    pub rolling_returns: Option<RollingReturns>,
+    pub as_of: Option<AsOfValuation>,
+    pub snapshots: Vec<WeeklySnapshot>,
+    pub ending_positions: Vec<Position>,
+    pub benchmark_percentage_gain: Option<Decimal>,
+    pub vol_target_exposure: Option<Decimal>, // Invested fraction under VOL_TARGET
+    pub active_risk: Option<ActiveRisk>,       // Versus BENCHMARK, from trailing weekly returns
+    pub hedged: Option<HedgedPerformance>,     // Trailing returns net of BENCHMARK, week by week
+    pub max_win_streak: u32,                   // Most consecutive up weeks
+    pub max_loss_streak: u32,                  // Most consecutive down weeks
+    pub withdrawn: Decimal,                    // Total taken out under WITHDRAW
+    pub depleted_week: Option<u32>,            // Week WITHDRAW emptied the portfolio
+    pub yearly_returns: Vec<(i32, Decimal)>,   // Calendar year and its return as a fraction
+    pub initial_holdings_value: Decimal,       // Existing portfolio marked to market at the start
+    pub twrr: Decimal,                         // Time-weighted return, net of WITHDRAW cash flows
+    pub ulcer_index: Decimal,                  // RMS drawdown from the running peak, in percentage points
+    pub value_at_risk: Option<ValueAtRisk>,    // Under VAR
+}
+
+/// A company's share of the ending portfolio, in proportion to its pattern
+/// weight. Unweighted entries count equally, so a company listed twice in the
+/// rotation gets twice the slice.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Position {
+    pub company: String,
+    pub ticker: String,
+    pub shares: Decimal,
+    pub value: Decimal,
+    pub contribution: Decimal, // Share of the total gain
+}
+
+/// Portfolio state at the end of a simulated week
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeeklySnapshot {
+    pub week: u32,
+    pub date: NaiveDate,
+    pub cash: Decimal,
+    pub holdings: Vec<Holding>,
+    pub total_value: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holding {
+    pub ticker: String,
+    pub shares: Decimal,
+    pub price: Decimal, // Entry price carried forward at the model's growth rate
+    pub value: Decimal,
+}
+
+#[derive(Serialize)]
+struct SnapshotExport<'a> {
+    pattern: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    initial_amount: Decimal,
+    time_frame: String,
+    currency: &'a str,
+    snapshots: &'a [WeeklySnapshot],
+}
+
+/// Pairwise correlation of weekly returns between a pattern's constituents
+#[derive(Debug)]
+pub struct CorrelationMatrix {
+    pub pattern_name: String,
+    pub tickers: Vec<String>,
+    pub values: Vec<Vec<Option<Decimal>>>, // None where a pair lacks enough data
 }
 
+/// Where a ticker's entry price and return history came from. Every simulated
+/// week holding the ticker trades at this one entry price.
 #[derive(Debug)]
+pub struct PriceProvenance {
+    pub ticker: String,
+    pub price: Decimal,
+    pub source: String,
+    pub bars: usize,
+    pub first_close: Option<(NaiveDate, Decimal)>, // Earliest bar behind the annual return
+    pub last_close: Option<(NaiveDate, Decimal)>,  // Latest bar behind the annual return
+}
+
+/// Percentage gain of each pattern and time frame at every invest amount
+#[derive(Debug)]
+pub struct AmountTable {
+    pub amounts: Vec<Decimal>, // Column order, ascending
+    pub rows: Vec<AmountRow>,
+}
+
+#[derive(Debug)]
+pub struct AmountRow {
+    pub pattern_name: String,
+    pub time_frame: String,
+    pub gains: Vec<Option<Decimal>>, // One per amount; None where that combination wasn't run
+}
+
+/// One simulation rerun under a named FEE_MODEL
+#[derive(Debug)]
+pub struct FeeComparison {
+    pub fee_model: String,
+    pub pattern_name: String,
+    pub initial_amount: Decimal,
+    pub time_frame: String,
+    pub total_fees: Decimal,
+    pub final_amount: Decimal, // Net of the fees and the growth they forgo
+}
+
+/// An existing portfolio to continue from instead of starting in cash, read
+/// from JSON such as `{"cash": 500, "shares": {"AAPL": 10, "MSFT": 4.5}}`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InitialHoldings {
+    #[serde(default)]
+    pub cash: Decimal,
+    #[serde(default)]
+    pub shares: HashMap<String, Decimal>,
+}
+
+impl InitialHoldings {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read holdings file {:?}: {}", path, e))?;
+        let holdings = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid holdings file {:?}: {}", path, e))?;
+        Ok(holdings)
+    }
+}
+
+/// Mark-to-market of the simulated portfolio at a date inside the simulation window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AsOfValuation {
+    pub date: NaiveDate,
+    pub week: u32,
+    pub holding: String,
+    pub market_value: Decimal,
+    pub cost_basis: Decimal,
+    pub unrealized_gain: Decimal,
+}
+
+/// One-week value at risk of the final portfolio, from the pattern's trailing
+/// weekly returns scaled by its exposure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValueAtRisk {
+    pub confidence: Decimal,
+    pub method: VarMethod,
+    pub loss: Decimal,   // Fraction of the portfolio
+    pub amount: Decimal, // The same loss in money
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     pub week: u32,
+    pub date: NaiveDate, // Start of the trade's week
     pub company: String,
+    pub ticker: String,
     pub price: Decimal,
     pub shares_bought: Decimal,
     pub amount_invested: Decimal,
+    pub fee: Decimal, // Paid out of amount_invested, so it buys no shares
+}
+
+impl Trade {
+    /// Broker action for the trade: shares bought are a BUY, shares given up a SELL
+    pub fn action(&self) -> &'static str {
+        if self.shares_bought < Decimal::ZERO { "SELL" } else { "BUY" }
+    }
+}
+
+/// Projects portfolio growth from an average annual return over a fixed horizon.
+/// The regime (normal compounding vs. conservative long-term) is picked once from
+/// the full horizon so every point on the equity curve follows the same model.
+struct GrowthModel {
+    annual_return: Decimal,
+    long_term: bool,
+}
+
+impl GrowthModel {
+    fn new(avg_annual_return: Decimal, total_years: Decimal) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if total_years <= Decimal::from(5) {
+            // For periods ≤ 5 years, allow normal compound growth
+            return Ok(GrowthModel {
+                annual_return: avg_annual_return,
+                long_term: false,
+            });
+        }
+
+        // For periods > 5 years, use more conservative modeling
+        // Real market volatility and mean reversion make sustained high returns unlikely
+
+        // Cap effective annual return for long periods (market reversion)
+        let long_term_return = if avg_annual_return > Decimal::try_from(0.15)? {
+            // Even great stocks revert towards ~15% long-term
+            Decimal::try_from(0.15)?
+        } else if avg_annual_return < Decimal::try_from(-0.1)? {
+            // Floor at -10% long-term (market recovery)
+            Decimal::try_from(-0.1)?
+        } else {
+            avg_annual_return
+        };
+
+        Ok(GrowthModel {
+            annual_return: long_term_return,
+            long_term: true,
+        })
+    }
+
+    /// Value at the start of each week plus the final value; the last point
+    /// always lands on the exact horizon (day-based frames round weeks up).
+    /// The initial amount is deployed in `tranches` equal parts at the start of
+    /// consecutive weeks, each growing from its own deployment; the rest waits as cash.
+    fn equity_curve(&self, initial_amount: Decimal, total_weeks: u32, total_years: Decimal, tranches: u32) -> Vec<Decimal> {
+        let elapsed_years = |week: u32| Self::elapsed_years(week, total_weeks, total_years);
+        let tranche = initial_amount / Decimal::from(tranches);
+
+        (0..=total_weeks).map(|week| {
+            (0..tranches).map(|deployed_at| {
+                if week < deployed_at {
+                    tranche
+                } else {
+                    tranche * self.factor((elapsed_years(week) - elapsed_years(deployed_at)).max(Decimal::ZERO))
+                }
+            }).sum()
+        }).collect()
+    }
+
+    /// Value at each curve point of `amount` invested at the start of week
+    /// `from_week`, and nothing before it
+    fn grown_from(&self, amount: Decimal, from_week: u32, total_weeks: u32, total_years: Decimal) -> Vec<Decimal> {
+        let deployed_at = Self::elapsed_years(from_week, total_weeks, total_years);
+        (0..=total_weeks).map(|week| {
+            if week < from_week {
+                Decimal::ZERO
+            } else {
+                amount * self.factor((Self::elapsed_years(week, total_weeks, total_years) - deployed_at).max(Decimal::ZERO))
+            }
+        }).collect()
+    }
+
+    /// Years elapsed at the start of `week`, landing exactly on the horizon at the end
+    fn elapsed_years(week: u32, total_weeks: u32, total_years: Decimal) -> Decimal {
+        if week == total_weeks {
+            total_years
+        } else {
+            total_years * Decimal::from(week) / Decimal::from(total_weeks)
+        }
+    }
+
+    /// Growth multiplier after `years` have elapsed
+    fn factor(&self, years: Decimal) -> Decimal {
+        if self.long_term {
+            // Use linear approximation for very long periods to avoid exponential explosion
+            return Decimal::ONE + (self.annual_return * years);
+        }
+
+        let annual_multiplier = Decimal::ONE + self.annual_return;
+        let mut compound_factor = Decimal::ONE;
+        let whole_years = years.floor();
+
+        for _ in 0..whole_years.to_u32().unwrap_or(0) {
+            compound_factor *= annual_multiplier;
+        }
+
+        let fractional_year = years - whole_years;
+        if fractional_year > Decimal::ZERO {
+            compound_factor *= Decimal::ONE + (self.annual_return * fractional_year);
+        }
+        compound_factor
+    }
+}
+
+/// Holds back `buffer` of whatever would be invested at each week as cash,
+/// rebalancing weekly, so positions never exceed `1 - buffer` of the portfolio.
+/// The reserve earns nothing, so each week's return shrinks by the same share.
+fn apply_cash_buffer(curve: &mut [Decimal], idle_cash: &mut [Decimal], buffer: Decimal) {
+    let invested_share = Decimal::ONE - buffer;
+    let original = curve.to_vec();
+    for week in 0..curve.len() {
+        if week > 0 {
+            curve[week] = if original[week - 1] > Decimal::ZERO {
+                curve[week - 1] * (Decimal::ONE + invested_share * (original[week] / original[week - 1] - Decimal::ONE))
+            } else {
+                curve[week - 1]
+            };
+        }
+        let invested_fraction = if original[week] > Decimal::ZERO {
+            (original[week] - idle_cash[week]) / original[week]
+        } else {
+            Decimal::ZERO
+        };
+        idle_cash[week] = curve[week] * (Decimal::ONE - invested_share * invested_fraction);
+    }
+}
+
+/// Takes scheduled withdrawals out of an equity curve, week by week. The rest
+/// of the portfolio keeps the curve's weekly growth, and each withdrawal sells
+/// cash and holdings pro rata. Returns the amount withdrawn at each curve
+/// point and the week the portfolio ran out, after which it stays empty.
+fn apply_withdrawals(
+    curve: &mut [Decimal],
+    idle_cash: &mut [Decimal],
+    withdrawal: &Withdrawal,
+) -> (Vec<Decimal>, Option<u32>) {
+    let mut withdrawn = vec![Decimal::ZERO; curve.len()];
+    let mut depleted_week = None;
+    let mut previous = curve[0];
+    for week in 1..curve.len() {
+        let original = curve[week];
+        let mut value = if depleted_week.is_some() || previous <= Decimal::ZERO {
+            Decimal::ZERO
+        } else {
+            curve[week - 1] * original / previous
+        };
+        previous = original;
+
+        if withdrawal.due(week as u32) && depleted_week.is_none() {
+            let taken = withdrawal.amount.min(value);
+            withdrawn[week] = taken;
+            value -= taken;
+            if value <= Decimal::ZERO {
+                depleted_week = Some(week as u32);
+            }
+        }
+
+        idle_cash[week] = if original > Decimal::ZERO {
+            idle_cash[week] * value / original
+        } else {
+            Decimal::ZERO
+        };
+        curve[week] = value;
+    }
+    (withdrawn, depleted_week)
+}
+
+/// Reads `ticker,price` rows. Blank lines, `#` comments and a header row are skipped.
+fn read_prices_file(path: &Path) -> Result<Vec<(String, Decimal)>, Box<dyn Error + Send + Sync>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read prices file {:?}: {}", path, e))?;
+
+    let mut prices = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (ticker, price) = line.split_once(',')
+            .ok_or(format!("{:?} line {}: expected ticker,price", path, index + 1))?;
+        match price.trim().parse::<Decimal>() {
+            Ok(price) => prices.push((ticker.trim().to_string(), price)),
+            Err(_) if index == 0 => continue, // Header row
+            Err(e) => return Err(format!("{:?} line {}: invalid price '{}': {}", path, index + 1, price.trim(), e).into()),
+        }
+    }
+    Ok(prices)
+}
+
+/// Drops the parts of serialized results that depend on the calendar rather
+/// than the run: `date` fields and the calendar-year return split
+fn strip_calendar(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            fields.remove("date");
+            fields.remove("yearly_returns");
+            fields.values_mut().for_each(strip_calendar);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_calendar),
+        _ => {}
+    }
 }
 
 pub struct Simulator {
-    yahoo_client: YahooFinanceClient,
+    provider: Box<dyn StockDataProvider>,
+    start_date: NaiveDate, // Week 1 starts on this date
+    as_of: Option<NaiveDate>,
+    base_currency: Option<String>,
+    checkpoint: Option<(PathBuf, String)>, // Checkpoint file and the DSL content hash
+    initial_holdings: Option<InitialHoldings>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Simulator {
     pub fn new() -> Self {
+        Self::with_provider(YahooFinanceClient::new())
+    }
+
+    pub fn with_provider(provider: impl StockDataProvider + 'static) -> Self {
         Simulator {
-            yahoo_client: YahooFinanceClient::new(),
+            provider: Box::new(provider),
+            start_date: Utc::now().date_naive(),
+            as_of: None,
+            base_currency: None,
+            checkpoint: None,
+            initial_holdings: None,
         }
     }
 
+    /// Save each completed combination to `path` and skip those already saved there
+    /// for the same DSL content (identified by `dsl_hash`), start date and as-of date
+    pub fn with_checkpoint(mut self, path: PathBuf, dsl_hash: String) -> Self {
+        self.checkpoint = Some((path, dsl_hash));
+        self
+    }
+
+    /// `dsl_hash` combined with the simulator settings that change results, so a
+    /// checkpoint is only resumed under the same start date and as-of date
+    fn checkpoint_hash(&self, dsl_hash: &str) -> String {
+        let settings = format!("{} start={} as_of={:?}", dsl_hash, self.start_date, self.as_of);
+        checkpoint::content_hash(settings.as_bytes())
+    }
+
+    /// Simulates against fixed current prices per ticker without any network access
+    pub fn with_prices(prices: HashMap<String, Decimal>) -> Self {
+        Self::with_provider(FixedPriceProvider::new(prices))
+    }
+
+    /// Require every ticker to trade in `currency` before simulating
+    pub fn with_base_currency(mut self, currency: String) -> Self {
+        self.base_currency = Some(currency);
+        self
+    }
+
+    /// Start every simulation from an existing portfolio: its shares are marked
+    /// to market and grow with the pattern from week 1, its cash stays idle,
+    /// and the INVEST amount is new money on top
+    pub fn with_initial_holdings(mut self, holdings: InitialHoldings) -> Self {
+        self.initial_holdings = Some(holdings);
+        self
+    }
+
+    /// Start week 1 on `date` instead of today, so trade dates, yearly returns
+    /// and as-of valuations don't depend on the day the run happens
+    pub fn with_start_date(mut self, date: NaiveDate) -> Self {
+        self.start_date = date;
+        self
+    }
+
+    /// Also value every simulated portfolio as of `date`
+    pub fn with_as_of(mut self, date: NaiveDate) -> Self {
+        self.as_of = Some(date);
+        self
+    }
+
     pub async fn run_simulations(&mut self, dsl: &StockDSL) -> Result<Vec<SimulationResult>, Box<dyn Error + Send + Sync>> {
         let mut results = Vec::new();
 
-        // Pre-fetch all stock data to populate cache
-        for investment in dsl.investments.values() {
-            self.yahoo_client.get_stock_data(&investment.ticker).await?;
+        if let Some(date) = self.as_of
+            && date < self.start_date
+        {
+            return Err(format!("As-of date {} is before the simulation start {}", date, self.start_date).into());
+        }
+
+        // Seed user-supplied prices so those tickers skip the network
+        if let Some(path) = &dsl.prices_file {
+            for (ticker, price) in read_prices_file(path)? {
+                self.provider.seed_price(&ticker, price);
+            }
+        }
+
+        // Pre-fetch all stock data to populate cache, in a fixed order so the
+        // same ticker is reported when several fail
+        let mut tickers: Vec<String> = dsl.investments.values().map(|inv| inv.ticker.clone()).collect();
+        tickers.sort();
+        tickers.extend(dsl.benchmark.iter().map(|(ticker, _)| ticker.clone()));
+        let mut held_tickers: Vec<String> = self.initial_holdings.iter()
+            .flat_map(|holdings| holdings.shares.keys().cloned())
+            .collect();
+        held_tickers.sort();
+        tickers.extend(held_tickers);
+        self.provider.prefetch(&tickers).await?;
+
+        if let Some(base_currency) = self.base_currency.clone() {
+            self.check_currencies(&tickers, &base_currency).await?;
+        }
+        self.warn_data_gaps(dsl).await?;
+
+        let mut checkpoint = self.checkpoint.as_ref()
+            .map(|(path, dsl_hash)| Checkpoint::load(path, &self.checkpoint_hash(dsl_hash)));
+        if let Some(checkpoint) = &checkpoint
+            && !checkpoint.results.is_empty()
+        {
+            eprintln!("Resuming from checkpoint with {} completed simulations", checkpoint.results.len());
         }
 
         // Run simulations for each test pattern
@@ -50,13 +520,25 @@ impl Simulator {
                 // Run simulation for each combination of invest amount and time frame
                 for &invest_amount in &dsl.invest_amounts {
                     for time_frame in &dsl.time_frames {
+                        if let Some(completed) = checkpoint.as_ref()
+                            .and_then(|c| c.find(test_name, invest_amount, time_frame))
+                        {
+                            results.push(completed.clone());
+                            continue;
+                        }
+
                         let result = self.simulate_pattern(
                             test_name,
                             pattern,
                             invest_amount,
                             time_frame,
-                            &dsl.investments,
+                            dsl,
                         ).await?;
+
+                        if let (Some(checkpoint), Some((path, _))) = (checkpoint.as_mut(), &self.checkpoint) {
+                            checkpoint.results.push(result.clone());
+                            checkpoint.save(path)?;
+                        }
                         results.push(result);
                     }
                 }
@@ -66,22 +548,160 @@ impl Simulator {
         Ok(results)
     }
 
+    /// Reports the native currency of every ticker the run touches (investments,
+    /// benchmark legs and initial holdings) and refuses to mix currencies,
+    /// since there are no FX rates to convert them into the base currency.
+    /// Tickers with seeded prices carry no currency and are taken as the base.
+    async fn check_currencies(&mut self, tickers: &[String], base_currency: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut tickers: Vec<&String> = tickers.iter().collect();
+        tickers.sort();
+        tickers.dedup();
+
+        eprintln!("Base currency: {}", base_currency);
+        let mut mismatched = Vec::new();
+        for ticker in tickers {
+            let stock_data = self.provider.get_stock_data(ticker).await?;
+            let currency = stock_data.currency.as_deref().unwrap_or(base_currency);
+            eprintln!("  {}: {}", ticker, currency);
+            if !currency.eq_ignore_ascii_case(base_currency) {
+                mismatched.push(format!("{} ({})", ticker, currency));
+            }
+        }
+
+        if !mismatched.is_empty() {
+            return Err(format!(
+                "No FX rate available to convert into {}: {}",
+                base_currency,
+                mismatched.join(", ")
+            ).into());
+        }
+        Ok(())
+    }
+
+    /// Prints a warning for each gap or flat run in every ticker's history
+    async fn warn_data_gaps(&mut self, dsl: &StockDSL) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut tickers: Vec<&String> = dsl.investments.keys()
+            .chain(dsl.benchmark.iter().map(|(ticker, _)| ticker))
+            .collect();
+        tickers.sort();
+        tickers.dedup();
+
+        for ticker in tickers {
+            let stock_data = self.provider.get_stock_data(ticker).await?;
+            for gap in stock_data.detect_gaps(chrono::Duration::days(MAX_DATA_GAP_DAYS)) {
+                eprintln!("Warning: {} {}", ticker, gap);
+            }
+        }
+        Ok(())
+    }
+
+    /// Entry price source and history span of each tested pattern's tickers
+    pub async fn price_provenance(&mut self, dsl: &StockDSL) -> Result<Vec<PriceProvenance>, Box<dyn Error + Send + Sync>> {
+        let mut tickers: Vec<String> = dsl.tests.iter()
+            .filter_map(|test_name| dsl.patterns.get(test_name))
+            .flatten()
+            .filter_map(|company_name| dsl.investments.values().find(|inv| inv.name == *company_name))
+            .map(|investment| investment.ticker.clone())
+            .collect();
+        tickers.sort();
+        tickers.dedup();
+
+        let mut provenance = Vec::new();
+        for ticker in tickers {
+            let stock_data = self.provider.get_stock_data(&ticker).await?;
+            let first = stock_data.historical_prices.iter().min_by_key(|p| p.date);
+            let last = stock_data.historical_prices.iter().max_by_key(|p| p.date);
+            provenance.push(PriceProvenance {
+                price: stock_data.entry_price(dsl.entry_price),
+                source: stock_data.entry_price_source(dsl.entry_price),
+                bars: stock_data.historical_prices.len(),
+                first_close: first.map(|bar| (bar.date.date_naive(), bar.close)),
+                last_close: last.map(|bar| (bar.date.date_naive(), bar.close)),
+                ticker,
+            });
+        }
+        Ok(provenance)
+    }
+
+    /// Reruns every tested simulation under each FEE_MODEL in place of FEE, the
+    /// models for one simulation side by side
+    pub async fn compare_fees(&mut self, dsl: &StockDSL) -> Result<Vec<FeeComparison>, Box<dyn Error + Send + Sync>> {
+        let mut comparisons = Vec::new();
+        let mut priced = dsl.clone();
+
+        for test_name in &dsl.tests {
+            let Some(pattern) = dsl.patterns.get(test_name) else {
+                continue;
+            };
+            for &invest_amount in &dsl.invest_amounts {
+                for time_frame in &dsl.time_frames {
+                    for (fee_model, fee) in &dsl.fee_models {
+                        priced.fee = Some(*fee);
+                        let result = self.simulate_pattern(test_name, pattern, invest_amount, time_frame, &priced).await?;
+                        comparisons.push(FeeComparison {
+                            fee_model: fee_model.clone(),
+                            pattern_name: result.pattern_name,
+                            initial_amount: result.initial_amount,
+                            time_frame: result.time_frame.to_string(),
+                            total_fees: result.trades.iter().map(|trade| trade.fee).sum(),
+                            final_amount: result.final_amount,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(comparisons)
+    }
+
+    /// Correlation matrix of each tested pattern's distinct tickers
+    pub async fn correlation_matrices(&mut self, dsl: &StockDSL) -> Result<Vec<CorrelationMatrix>, Box<dyn Error + Send + Sync>> {
+        let mut matrices = Vec::new();
+
+        for test_name in &dsl.tests {
+            let Some(pattern) = dsl.patterns.get(test_name) else {
+                continue;
+            };
+
+            let mut tickers: Vec<String> = Vec::new();
+            for company_name in pattern {
+                if let Some(investment) = dsl.investments.values().find(|inv| inv.name == *company_name)
+                    && !tickers.contains(&investment.ticker)
+                {
+                    tickers.push(investment.ticker.clone());
+                }
+            }
+
+            let mut returns = Vec::new();
+            for ticker in &tickers {
+                returns.push(self.provider.get_stock_data(ticker).await?.weekly_returns(dsl.end_align));
+            }
+
+            let values = returns.iter()
+                .map(|a| returns.iter().map(|b| metrics::correlation(a, b)).collect())
+                .collect();
+
+            matrices.push(CorrelationMatrix {
+                pattern_name: test_name.clone(),
+                tickers,
+                values,
+            });
+        }
+
+        Ok(matrices)
+    }
+
     async fn simulate_pattern(
         &mut self,
         pattern_name: &str,
         pattern: &[String],
         initial_amount: Decimal,
         time_frame: &TimeFrame,
-        investments: &HashMap<String, crate::dsl::Investment>,
+        dsl: &StockDSL,
     ) -> Result<SimulationResult, Box<dyn Error + Send + Sync>> {
-        let mut current_amount = initial_amount;
+        let investments = &dsl.investments;
         let mut trades = Vec::new();
 
-        let total_weeks = match time_frame.unit {
-            TimeUnit::Days => (time_frame.duration + 6) / 7, // Round up to nearest week
-            TimeUnit::Weeks => time_frame.duration,
-            TimeUnit::Years => time_frame.duration * 52,
-        };
+        let total_weeks = time_frame.total_weeks();
 
         if pattern.is_empty() {
             return Err("Empty pattern".into());
@@ -91,9 +711,20 @@ impl Simulator {
         let total_years = match time_frame.unit {
             TimeUnit::Days => Decimal::try_from(time_frame.duration as f64 / 365.25)?,
             TimeUnit::Weeks => Decimal::try_from(time_frame.duration as f64 / 52.0)?,
+            TimeUnit::Months => Decimal::from(time_frame.duration) / Decimal::from(12),
             TimeUnit::Years => Decimal::from(time_frame.duration),
         };
 
+        // Ticker and entry price held during each week
+        let mut held = Vec::with_capacity(total_weeks as usize);
+
+        // Initial capital goes in over this many weeks, never past the horizon
+        let tranches = match dsl.initial_deploy {
+            InitialDeploy::Immediate => 1,
+            InitialDeploy::Spread(weeks) => weeks.clamp(1, total_weeks.max(1)),
+        };
+        let tranche_amount = initial_amount / Decimal::from(tranches);
+
         // Simulate weekly trading but apply realistic annual returns
         for week in 1..=total_weeks {
             let company_index = ((week - 1) as usize) % pattern.len();
@@ -104,84 +735,175 @@ impl Simulator {
                 .find(|inv| inv.name == *company_name)
                 .ok_or(format!("Investment not found for company: {}", company_name))?;
 
-            // Get current stock price from cache
-            let stock_data = self.yahoo_client.get_stock_data(&investment.ticker).await?;
-            let stock_price = stock_data.current_price;
+            // Get the entry price from cache
+            let stock_data = self.provider.get_stock_data(&investment.ticker).await?;
+            let stock_price = stock_data.entry_price(dsl.entry_price);
+            held.push((investment.ticker.clone(), stock_price));
 
-            // For the first trade, record the initial investment details
-            if week == 1 {
-                let shares_to_buy = current_amount / stock_price;
+            // Record each deployment of the initial capital
+            if week <= tranches {
+                let fee = dsl.fee.map_or(Decimal::ZERO, |fee| fee.on(tranche_amount));
+                let shares_to_buy = (tranche_amount - fee) / stock_price;
                 trades.push(Trade {
                     week,
+                    date: self.start_date + Days::new(7 * (week - 1) as u64),
                     company: company_name.clone(),
+                    ticker: investment.ticker.clone(),
                     price: stock_price,
                     shares_bought: shares_to_buy,
-                    amount_invested: current_amount,
+                    amount_invested: tranche_amount,
+                    fee,
                 });
+
+                if let Some(max_trades) = dsl.max_trades
+                    && trades.len() > max_trades
+                {
+                    return Err(format!(
+                        "Pattern {} ({}, {}) exceeded MAX_TRADES of {} at week {}; the strategy may be unstable",
+                        pattern_name, initial_amount, time_frame, max_trades, week
+                    ).into());
+                }
             }
         }
 
-        // Apply realistic compound growth over the entire time period
-        if total_years > Decimal::ZERO {
-            // Get a weighted average annual return from all stocks in the pattern
-            let mut total_weighted_return = Decimal::ZERO;
-            let mut total_weight = Decimal::ZERO;
-            
-            for company_name in pattern {
-                if let Some(investment) = investments.values().find(|inv| inv.name == *company_name) {
-                    let annual_return = self.yahoo_client.calculate_annual_return(&investment.ticker)?;
-                    total_weighted_return += annual_return;
-                    total_weight += Decimal::ONE;
-                }
+        // Get a weighted average annual return from all stocks in the pattern
+        let weights = dsl.entry_weights(pattern_name);
+        let mut total_weighted_return = Decimal::ZERO;
+        let mut total_weight = Decimal::ZERO;
+
+        for (company_name, weight) in pattern.iter().zip(&weights) {
+            if let Some(investment) = investments.values().find(|inv| inv.name == *company_name) {
+                let annual_return = self.annual_return(&investment.ticker, dsl).await?;
+                total_weighted_return += annual_return * weight;
+                total_weight += weight;
             }
-            
-            let avg_annual_return = if total_weight > Decimal::ZERO {
-                total_weighted_return / total_weight
-            } else {
-                Decimal::ZERO
-            };
+        }
 
-            // Apply compound growth with realistic bounds
-            let growth_factor = if total_years <= Decimal::from(5) {
-                // For periods ≤ 5 years, allow normal compound growth
-                let annual_multiplier = Decimal::ONE + avg_annual_return;
-                let mut compound_factor = Decimal::ONE;
-                let whole_years = total_years.floor();
-                
-                for _ in 0..whole_years.to_u32().unwrap_or(0) {
-                    compound_factor *= annual_multiplier;
-                }
-                
-                let fractional_year = total_years - whole_years;
-                if fractional_year > Decimal::ZERO {
-                    compound_factor *= Decimal::ONE + (avg_annual_return * fractional_year);
-                }
-                compound_factor
-            } else {
-                // For periods > 5 years, use more conservative modeling
-                // Real market volatility and mean reversion make sustained high returns unlikely
-                
-                // Cap effective annual return for long periods (market reversion)
-                let long_term_return = if avg_annual_return > Decimal::try_from(0.15)? {
-                    // Even great stocks revert towards ~15% long-term
-                    Decimal::try_from(0.15)?
-                } else if avg_annual_return < Decimal::try_from(-0.1)? {
-                    // Floor at -10% long-term (market recovery)
-                    Decimal::try_from(-0.1)?
-                } else {
-                    avg_annual_return
-                };
-                
-                // Use linear approximation for very long periods to avoid exponential explosion
-                Decimal::ONE + (long_term_return * total_years)
-            };
-            
-            current_amount *= growth_factor;
+        let avg_annual_return = if total_weight > Decimal::ZERO {
+            total_weighted_return / total_weight
+        } else {
+            Decimal::ZERO
+        };
+
+        // Apply realistic compound growth over the entire time period, week by week
+        let growth = GrowthModel::new(avg_annual_return, total_years)?;
+        let equity_curve = growth.equity_curve(initial_amount, total_weeks, total_years, tranches);
+
+        // An existing portfolio is fully invested from the start, apart from its cash
+        let (held_value, held_cash) = self.initial_holdings_value(dsl).await?;
+        let starting_value = initial_amount + held_value + held_cash;
+        let equity_curve: Vec<Decimal> = if held_value > Decimal::ZERO {
+            equity_curve.iter()
+                .zip(growth.equity_curve(held_value, total_weeks, total_years, 1))
+                .map(|(new_money, held)| new_money + held)
+                .collect()
+        } else {
+            equity_curve
+        };
+
+        // Scale exposure to hit the volatility target, holding the rest as idle cash
+        let vol_target_exposure = match dsl.vol_target {
+            Some(target) => Some(self.vol_target_exposure(pattern, &weights, dsl, target).await?),
+            None => None,
+        };
+        let exposure = vol_target_exposure.unwrap_or(Decimal::ONE);
+        let cash = (initial_amount + held_value) * (Decimal::ONE - exposure) + held_cash;
+        let mut equity_curve: Vec<Decimal> = equity_curve.iter()
+            .map(|value| cash + value * exposure)
+            .collect();
+        // Uninvested cash at each curve point: the volatility-target reserve plus
+        // the exposed share of initial capital not yet deployed
+        let mut idle_cash: Vec<Decimal> = (0..=total_weeks).map(|week| {
+            let undeployed = tranche_amount * Decimal::from(tranches - week.min(tranches));
+            cash + undeployed * exposure
+        }).collect();
+        // Each fee comes out of its trade before it's invested: the exposed share
+        // also loses the growth it would have earned, the rest leaves idle cash
+        for trade in &trades {
+            let from_week = trade.week - 1;
+            let from_cash = trade.fee * (Decimal::ONE - exposure);
+            let forgone = growth.grown_from(trade.fee * exposure, from_week, total_weeks, total_years);
+            for (week, invested_fee) in forgone.into_iter().enumerate().skip(from_week as usize) {
+                equity_curve[week] -= invested_fee + from_cash;
+                idle_cash[week] -= from_cash;
+            }
         }
+        if let Some(buffer) = dsl.cash_buffer {
+            apply_cash_buffer(&mut equity_curve, &mut idle_cash, buffer);
+        }
+        let (withdrawals, depleted_week) = match &dsl.withdrawal {
+            Some(withdrawal) => apply_withdrawals(&mut equity_curve, &mut idle_cash, withdrawal),
+            None => (vec![Decimal::ZERO; equity_curve.len()], None),
+        };
+        let withdrawn = withdrawals.iter().sum();
+        let cash_flows: Vec<Decimal> = withdrawals.iter().map(|w| -w).collect();
+        let twrr = metrics::time_weighted_return(&equity_curve, &cash_flows);
+        let current_amount = equity_curve[equity_curve.len() - 1];
+
+        let benchmark_percentage_gain = if dsl.benchmark.is_empty() {
+            None
+        } else {
+            let benchmark_return = self.benchmark_annual_return(dsl).await?;
+            let benchmark_growth = GrowthModel::new(benchmark_return, total_years)?;
+            Some((benchmark_growth.factor(total_years.max(Decimal::ZERO)) - Decimal::ONE) * Decimal::from(100))
+        };
+
+        // Idle cash earns nothing, so the portfolio's returns are the pattern's scaled by exposure
+        let (active_risk, hedged) = if dsl.benchmark.is_empty() {
+            (None, None)
+        } else {
+            let portfolio_returns: Vec<Decimal> = self.pattern_weekly_returns(pattern, &weights, dsl).await?
+                .iter()
+                .map(|r| r * exposure)
+                .collect();
+            let benchmark_returns = self.benchmark_weekly_returns(dsl).await?;
+            (
+                metrics::active_risk(&portfolio_returns, &benchmark_returns),
+                metrics::hedged_performance(&portfolio_returns, &benchmark_returns),
+            )
+        };
+
+        let value_at_risk = match dsl.value_at_risk {
+            Some(spec) => {
+                let portfolio_returns: Vec<Decimal> = self.pattern_weekly_returns(pattern, &weights, dsl).await?
+                    .iter()
+                    .map(|r| r * exposure)
+                    .collect();
+                let loss = match spec.method {
+                    VarMethod::Historical => metrics::historical_var(&portfolio_returns, spec.confidence),
+                    VarMethod::Parametric => metrics::parametric_var(&portfolio_returns, spec.confidence),
+                };
+                loss.map(|loss| ValueAtRisk {
+                    confidence: spec.confidence,
+                    method: spec.method,
+                    loss,
+                    amount: loss * current_amount,
+                })
+            }
+            None => None,
+        };
 
-        let total_gain = current_amount - initial_amount;
-        let percentage_gain = if initial_amount > Decimal::ZERO {
-            match (total_gain / initial_amount).checked_mul(Decimal::from(100)) {
+        let (max_win_streak, max_loss_streak) = metrics::streaks(&equity_curve);
+        let ulcer_index = metrics::ulcer_index(&equity_curve);
+
+        let yearly_returns = metrics::calendar_year_returns(self.start_date, &equity_curve);
+
+        let rolling_returns = dsl.rolling_window
+            .and_then(|window| metrics::rolling_returns(&equity_curve, window));
+
+        let snapshots = self.weekly_snapshots(&held, &equity_curve, starting_value, &idle_cash);
+
+        let as_of = self.as_of.and_then(|date| {
+            let valuation = self.value_as_of(date, pattern, &equity_curve, starting_value);
+            if valuation.is_none() {
+                eprintln!("Warning: as-of date {} is after the end of {} ({}); no valuation", date, pattern_name, time_frame);
+            }
+            valuation
+        });
+
+        let total_gain = current_amount - starting_value;
+        let percentage_gain = if starting_value > Decimal::ZERO {
+            match (total_gain / starting_value).checked_mul(Decimal::from(100)) {
                 Some(gain) => gain,
                 None => {
                     // Handle overflow in percentage calculation
@@ -196,57 +918,1461 @@ impl Simulator {
             Decimal::ZERO
         };
 
+        let ending_cash = idle_cash[idle_cash.len() - 1];
+        let ending_positions = self.ending_positions(
+            pattern, &weights, dsl, starting_value - cash, current_amount - ending_cash, total_gain,
+        ).await?;
+
         Ok(SimulationResult {
             pattern_name: pattern_name.to_string(),
+            description: dsl.descriptions.get(pattern_name).cloned(),
             initial_amount,
             time_frame: time_frame.clone(),
+            currency: dsl.currency.clone(),
             final_amount: current_amount,
             total_gain,
             percentage_gain,
             trades,
+            rolling_returns,
+            as_of,
+            snapshots,
+            ending_positions,
+            benchmark_percentage_gain,
+            vol_target_exposure,
+            active_risk,
+            hedged,
+            max_win_streak,
+            max_loss_streak,
+            withdrawn,
+            depleted_week,
+            yearly_returns,
+            initial_holdings_value: held_value + held_cash,
+            twrr,
+            ulcer_index,
+            value_at_risk,
         })
     }
 
-    pub fn print_results(results: &[SimulationResult]) {
-        println!("\n=== STOCK SIMULATION RESULTS ===\n");
+    /// Market value of the initial holdings' shares at their entry prices, and their cash
+    async fn initial_holdings_value(&mut self, dsl: &StockDSL) -> Result<(Decimal, Decimal), Box<dyn Error + Send + Sync>> {
+        let Some(holdings) = self.initial_holdings.clone() else {
+            return Ok((Decimal::ZERO, Decimal::ZERO));
+        };
 
-        for result in results {
-            println!("Pattern: {}", result.pattern_name);
-            println!("Initial Investment: ${:.2}", result.initial_amount);
-            println!("Time Frame: {} {:?}", result.time_frame.duration, result.time_frame.unit);
-            println!("Final Amount: ${:.2}", result.final_amount);
-            println!("Total Gain: ${:.2}", result.total_gain);
-            println!("Percentage Gain: {:.2}%", result.percentage_gain);
-            println!("Number of Trades: {}", result.trades.len());
-            
-            if !result.trades.is_empty() {
-                println!("Sample Trades:");
-                for trade in result.trades.iter().take(5) {
-                    println!("  Week {}: {} @ ${:.2} ({:.4} shares)",
-                        trade.week, trade.company, trade.price, trade.shares_bought);
-                }
-                if result.trades.len() > 5 {
-                    println!("  ... and {} more trades", result.trades.len() - 5);
-                }
-            }
-            println!("{}", "-".repeat(50));
+        let mut value = Decimal::ZERO;
+        for (ticker, shares) in &holdings.shares {
+            value += self.provider.get_stock_data(ticker).await?.entry_price(dsl.entry_price) * shares;
         }
+        Ok((value, holdings.cash))
+    }
 
-        // Summary statistics
-        if !results.is_empty() {
-            let best_result = results.iter()
-                .max_by(|a, b| a.percentage_gain.partial_cmp(&b.percentage_gain).unwrap())
-                .unwrap();
-            
-            let worst_result = results.iter()
-                .min_by(|a, b| a.percentage_gain.partial_cmp(&b.percentage_gain).unwrap())
-                .unwrap();
+    /// Fraction of the portfolio to invest so the pattern's trailing volatility
+    /// matches `target`, never above fully invested. A pattern whose volatility
+    /// can't be measured stays fully invested.
+    async fn vol_target_exposure(
+        &mut self,
+        pattern: &[String],
+        weights: &[Decimal],
+        dsl: &StockDSL,
+        target: Decimal,
+    ) -> Result<Decimal, Box<dyn Error + Send + Sync>> {
+        let returns = self.pattern_weekly_returns(pattern, weights, dsl).await?;
+        let realized = match dsl.ewma_lambda {
+            Some(lambda) => metrics::ewma_volatility(&returns, lambda),
+            None => metrics::annualized_volatility(&returns),
+        };
+        let exposure = match realized {
+            Some(realized) if realized > Decimal::ZERO => (target / realized).min(Decimal::ONE),
+            _ => Decimal::ONE,
+        };
+        Ok(exposure)
+    }
 
-            println!("\n=== SUMMARY ===");
-            println!("Best Performance: {} with {:.2}% gain", 
-                best_result.pattern_name, best_result.percentage_gain);
-            println!("Worst Performance: {} with {:.2}% gain", 
-                worst_result.pattern_name, worst_result.percentage_gain);
-        }
+    /// Expected annual return of a ticker: the capped trailing return, its
+    /// recency-weighted variant under EWMA_LAMBDA, or one between averaged
+    /// endpoints under RETURN_ENDPOINT trimmed, plus any DIVIDEND yield
+    async fn annual_return(&mut self, ticker: &str, dsl: &StockDSL) -> Result<Decimal, Box<dyn Error + Send + Sync>> {
+        let price_return = match (dsl.ewma_lambda, dsl.return_endpoint) {
+            (Some(lambda), _) => self.provider.get_stock_data(ticker).await?.ewma_annual_return(lambda, dsl.end_align),
+            (None, ReturnEndpoint::Trimmed(bars)) => self.provider.get_stock_data(ticker).await?.trimmed_annual_return(bars, dsl.end_align),
+            (None, ReturnEndpoint::Raw) => self.provider.calculate_annual_return(ticker, dsl.end_align),
+        }?;
+        let dividend_yield = dsl.investments.get(ticker)
+            .and_then(|investment| investment.dividend_yield)
+            .unwrap_or_default();
+        Ok(price_return + dividend_yield)
     }
-}
\ No newline at end of file
+
+    /// Trailing weekly returns of a pattern, each resolvable entry at its weight
+    async fn pattern_weekly_returns(
+        &mut self,
+        pattern: &[String],
+        weights: &[Decimal],
+        dsl: &StockDSL,
+    ) -> Result<Vec<Decimal>, Box<dyn Error + Send + Sync>> {
+        let mut series = Vec::new();
+        let mut series_weights = Vec::new();
+        for (company_name, weight) in pattern.iter().zip(weights) {
+            if let Some(investment) = dsl.investments.values().find(|inv| inv.name == *company_name) {
+                series.push(self.provider.get_stock_data(&investment.ticker).await?.weekly_returns(dsl.end_align));
+                series_weights.push(*weight);
+            }
+        }
+        Ok(metrics::weighted_returns(&series, &series_weights))
+    }
+
+    /// Trailing weekly returns of the benchmark blend, rebalanced to its weights
+    async fn benchmark_weekly_returns(
+        &mut self,
+        dsl: &StockDSL,
+    ) -> Result<Vec<Decimal>, Box<dyn Error + Send + Sync>> {
+        let mut series = Vec::new();
+        let mut weights = Vec::new();
+        for (ticker, weight) in &dsl.benchmark {
+            series.push(self.provider.get_stock_data(ticker).await?.weekly_returns(dsl.end_align));
+            weights.push(*weight);
+        }
+        Ok(metrics::weighted_returns(&series, &weights))
+    }
+
+    /// Weighted annual return of the benchmark blend. Holding the blend at fixed
+    /// weights means its return is the weight-averaged constituent return.
+    async fn benchmark_annual_return(&mut self, dsl: &StockDSL) -> Result<Decimal, Box<dyn Error + Send + Sync>> {
+        let mut weighted_return = Decimal::ZERO;
+        let mut total_weight = Decimal::ZERO;
+        for (ticker, weight) in &dsl.benchmark {
+            weighted_return += self.annual_return(ticker, dsl).await? * weight;
+            total_weight += weight;
+        }
+
+        Ok(if total_weight > Decimal::ZERO {
+            weighted_return / total_weight
+        } else {
+            Decimal::ZERO
+        })
+    }
+
+    async fn ending_positions(
+        &mut self,
+        pattern: &[String],
+        weights: &[Decimal],
+        dsl: &StockDSL,
+        initial_amount: Decimal,
+        final_amount: Decimal,
+        total_gain: Decimal,
+    ) -> Result<Vec<Position>, Box<dyn Error + Send + Sync>> {
+        let growth = if initial_amount > Decimal::ZERO {
+            final_amount / initial_amount
+        } else {
+            Decimal::ONE
+        };
+        // Only entries that resolve to an investment count, matching the average return
+        let resolved_weight: Decimal = pattern.iter().zip(weights)
+            .filter(|(c, _)| dsl.investments.values().any(|inv| inv.name == **c))
+            .map(|(_, weight)| weight)
+            .sum();
+
+        let mut positions: Vec<Position> = Vec::new();
+        for company_name in pattern {
+            if positions.iter().any(|p| p.company == *company_name) {
+                continue;
+            }
+            let Some(investment) = dsl.investments.values().find(|inv| inv.name == *company_name) else {
+                continue;
+            };
+
+            let company_weight: Decimal = pattern.iter().zip(weights)
+                .filter(|(c, _)| *c == company_name)
+                .map(|(_, weight)| weight)
+                .sum();
+            let weight = if resolved_weight > Decimal::ZERO {
+                company_weight / resolved_weight
+            } else {
+                Decimal::ZERO
+            };
+            let value = final_amount * weight;
+
+            let stock_data = self.provider.get_stock_data(&investment.ticker).await?;
+            let price = stock_data.entry_price(dsl.entry_price) * growth;
+            let shares = if price > Decimal::ZERO { value / price } else { Decimal::ZERO };
+
+            positions.push(Position {
+                company: company_name.clone(),
+                ticker: investment.ticker.clone(),
+                shares,
+                value,
+                contribution: total_gain * weight,
+            });
+        }
+
+        Ok(positions)
+    }
+
+    /// End-of-week state for every simulated week. The whole portfolio sits in
+    /// that week's pattern company (apart from any idle cash), marked at its entry
+    /// price grown by the same factor as the invested capital, so holdings plus
+    /// cash always sum to the equity curve.
+    fn weekly_snapshots(
+        &self,
+        held: &[(String, Decimal)],
+        equity_curve: &[Decimal],
+        initial_amount: Decimal,
+        idle_cash: &[Decimal],
+    ) -> Vec<WeeklySnapshot> {
+        held.iter().enumerate().map(|(i, (ticker, entry_price))| {
+            let week = i as u32 + 1;
+            let total_value = equity_curve[i + 1];
+            let cash = idle_cash[i + 1];
+            let invested_value = total_value - cash;
+            let growth = if initial_amount > cash {
+                invested_value / (initial_amount - cash)
+            } else {
+                Decimal::ONE
+            };
+            let price = *entry_price * growth;
+            let shares = if price > Decimal::ZERO { invested_value / price } else { Decimal::ZERO };
+
+            WeeklySnapshot {
+                week,
+                date: self.start_date + Days::new(7 * week as u64),
+                cash,
+                holdings: vec![Holding {
+                    ticker: ticker.clone(),
+                    shares,
+                    price,
+                    value: invested_value,
+                }],
+                total_value,
+            }
+        }).collect()
+    }
+
+    /// Writes every result's weekly snapshots to `path` as JSON
+    pub fn write_snapshots(results: &[SimulationResult], path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let export: Vec<SnapshotExport> = results.iter().map(|result| SnapshotExport {
+            pattern: &result.pattern_name,
+            description: result.description.as_deref(),
+            initial_amount: result.initial_amount,
+            time_frame: result.time_frame.to_string(),
+            currency: &result.currency,
+            snapshots: &result.snapshots,
+        }).collect();
+
+        serde_json::to_writer_pretty(File::create(path)?, &export)?;
+        Ok(())
+    }
+
+    /// Short hash of the serialized results, sorted so the order simulations
+    /// finished in doesn't matter. Calendar dates and calendar-year returns are
+    /// left out, since the start date defaults to today; dated entries still
+    /// carry their week index, so the same inputs and data give the same
+    /// fingerprint whichever day the run starts on.
+    pub fn fingerprint(results: &[SimulationResult]) -> String {
+        let mut sorted: Vec<&SimulationResult> = results.iter().collect();
+        sorted.sort_by(|a, b| {
+            a.pattern_name.cmp(&b.pattern_name)
+                .then(a.initial_amount.cmp(&b.initial_amount))
+                .then(a.time_frame.approx_days().cmp(&b.time_frame.approx_days()))
+                .then(a.time_frame.to_string().cmp(&b.time_frame.to_string()))
+        });
+        let mut payload = serde_json::to_value(&sorted).unwrap_or_default();
+        strip_calendar(&mut payload);
+        let serialized = serde_json::to_vec(&payload).unwrap_or_default();
+        checkpoint::content_hash(&serialized)
+    }
+
+    /// Values the portfolio at the last week boundary on or before `date`.
+    /// Dates outside the simulated window have no valuation.
+    fn value_as_of(
+        &self,
+        date: NaiveDate,
+        pattern: &[String],
+        equity_curve: &[Decimal],
+        initial_amount: Decimal,
+    ) -> Option<AsOfValuation> {
+        let elapsed_days = (date - self.start_date).num_days();
+        let total_weeks = equity_curve.len().saturating_sub(1);
+        if elapsed_days < 0 || total_weeks == 0 || elapsed_days as usize > total_weeks * 7 {
+            return None;
+        }
+
+        let weeks_elapsed = (elapsed_days / 7) as usize;
+        let market_value = equity_curve[weeks_elapsed];
+        // The position held during the week containing the date (the last week on the final day)
+        let holding_index = weeks_elapsed.min(total_weeks - 1) % pattern.len();
+
+        Some(AsOfValuation {
+            date,
+            week: (weeks_elapsed + 1).min(total_weeks) as u32,
+            holding: pattern[holding_index].clone(),
+            market_value,
+            cost_basis: initial_amount,
+            unrealized_gain: market_value - initial_amount,
+        })
+    }
+
+    /// `result pattern=... invest=... frame=... final=... gain=...% dd=...%`, with
+    /// unlocalized numbers so log lines stay greppable and diffable
+    pub fn oneline(result: &SimulationResult, options: &OutputOptions) -> String {
+        let curve: Vec<Decimal> = std::iter::once(result.initial_amount + result.initial_holdings_value)
+            .chain(result.snapshots.iter().map(|s| s.total_value))
+            .collect();
+        let drawdown = metrics::max_drawdown(&curve) * Decimal::from(100);
+
+        format!("result pattern={} invest={} frame={} final={} gain={}% dd={}%",
+            result.pattern_name,
+            format::plain_number(result.initial_amount, options.precision.money),
+            result.time_frame,
+            format::plain_number(result.final_amount, options.precision.money),
+            format::plain_number(result.percentage_gain, options.precision.percent),
+            format::plain_number(drawdown, options.precision.percent))
+    }
+
+    /// Every result's trades as `run,date,symbol,action,quantity,price,fee,amount`
+    /// rows, the column layout most brokers accept for transaction imports plus
+    /// the simulation each trade belongs to (`pattern/amount/frame`), so one
+    /// hypothetical run can be filtered out before importing. The amount is
+    /// quantity × price; the fee is charged on top of it.
+    pub fn broker_csv(results: &[SimulationResult], options: &OutputOptions) -> String {
+        let mut csv = String::from("run,date,symbol,action,quantity,price,fee,amount\n");
+        for result in results {
+            let run = format!("{}/{}/{}",
+                result.pattern_name,
+                format::plain_number(result.initial_amount, options.precision.money),
+                result.time_frame);
+            for trade in &result.trades {
+                let quantity = trade.shares_bought.abs();
+                csv.push_str(&format!("{},{},{},{},{},{},{},{}\n",
+                    run,
+                    trade.date,
+                    trade.ticker,
+                    trade.action(),
+                    format::plain_number(quantity, options.precision.shares),
+                    format::plain_number(trade.price, options.precision.money),
+                    format::plain_number(trade.fee, options.precision.money),
+                    format::plain_number(quantity * trade.price, options.precision.money)));
+            }
+        }
+        csv
+    }
+
+    pub fn print_results(results: &[SimulationResult], options: &OutputOptions) {
+        if let Err(e) = Self::write_results(&mut std::io::stdout().lock(), results, options) {
+            eprintln!("Error writing results: {}", e);
+        }
+    }
+
+    /// Writes results in the chosen output format to `out`
+    pub fn write_results(out: &mut impl Write, results: &[SimulationResult], options: &OutputOptions) -> std::io::Result<()> {
+        match options.format {
+            OutputFormat::Text => {}
+            OutputFormat::Oneline => {
+                for result in results {
+                    writeln!(out, "{}", Self::oneline(result, options))?;
+                }
+                return Ok(());
+            }
+            OutputFormat::BrokerCsv => {
+                write!(out, "{}", Self::broker_csv(results, options))?;
+                return Ok(());
+            }
+        }
+
+        if options.summary_only {
+            return Self::write_summary(out, results, options);
+        }
+
+        if let Some(banner) = options.banner_text("STOCK SIMULATION RESULTS") {
+            writeln!(out, "{}", banner)?;
+        }
+        if !options.quiet {
+            writeln!(out)?;
+        }
+
+        for result in results {
+            writeln!(out, "Pattern: {}", result.pattern_name)?;
+            if let Some(description) = &result.description {
+                writeln!(out, "Description: {}", description)?;
+            }
+            writeln!(out, "Initial Investment: {}", options.money(result.initial_amount))?;
+            if result.initial_holdings_value > Decimal::ZERO {
+                writeln!(out, "Initial Holdings: {}", options.money(result.initial_holdings_value))?;
+            }
+            writeln!(out, "Time Frame: {} {:?}", result.time_frame.duration, result.time_frame.unit)?;
+            writeln!(out, "Final Amount: {}", options.money(result.final_amount))?;
+            writeln!(out, "Total Gain: {}", options.gain(options.money(result.total_gain), result.total_gain))?;
+            writeln!(out, "Percentage Gain: {}", options.gain(options.percent(result.percentage_gain), result.percentage_gain))?;
+            if let Some(benchmark_gain) = result.benchmark_percentage_gain {
+                writeln!(out, "Benchmark Gain: {} (excess {})",
+                    options.percent(benchmark_gain),
+                    options.percent(result.percentage_gain - benchmark_gain))?;
+            }
+            if let Some(active) = &result.active_risk {
+                writeln!(out, "Information Ratio: {} (active return {}, tracking error {})",
+                    options.ratio(active.information_ratio),
+                    options.percent(active.active_return * Decimal::from(100)),
+                    options.percent(active.tracking_error * Decimal::from(100)))?;
+            }
+            if let Some(hedged) = &result.hedged {
+                writeln!(out, "Market-Neutral (trailing, net of benchmark): return {}, volatility {}, Sharpe {}, max drawdown {}",
+                    options.percent(hedged.total_return * Decimal::from(100)),
+                    options.percent(hedged.volatility * Decimal::from(100)),
+                    hedged.sharpe.map_or("n/a".to_string(), |sharpe| options.ratio(sharpe)),
+                    options.percent(hedged.max_drawdown * Decimal::from(100)))?;
+            }
+            if let Some(exposure) = result.vol_target_exposure {
+                writeln!(out, "Volatility Target Exposure: {}", options.percent(exposure * Decimal::from(100)))?;
+            }
+            if result.withdrawn > Decimal::ZERO {
+                match result.depleted_week {
+                    Some(week) => writeln!(out, "Withdrawn: {} (portfolio depleted in week {})", options.money(result.withdrawn), week)?,
+                    None => writeln!(out, "Withdrawn: {}", options.money(result.withdrawn))?,
+                }
+                writeln!(out, "Time-Weighted Return: {}",
+                    options.gain(options.percent(result.twrr * Decimal::from(100)), result.twrr))?;
+            }
+            writeln!(out, "Longest Streaks: {} up weeks, {} down weeks", result.max_win_streak, result.max_loss_streak)?;
+            writeln!(out, "Ulcer Index: {}", options.ratio(result.ulcer_index))?;
+            if let Some(var) = &result.value_at_risk {
+                writeln!(out, "Value at Risk ({} one week, {:?}): {} ({})",
+                    options.percent(var.confidence * Decimal::from(100)),
+                    var.method,
+                    options.percent(var.loss * Decimal::from(100)),
+                    options.money(var.amount))?;
+            }
+            if let Some(rolling) = &result.rolling_returns {
+                writeln!(out, "Rolling {}w Returns: min {}, median {}, max {}",
+                    rolling.window_weeks,
+                    options.percent(rolling.min * Decimal::from(100)),
+                    options.percent(rolling.median * Decimal::from(100)),
+                    options.percent(rolling.max * Decimal::from(100)))?;
+            }
+            if result.yearly_returns.len() > 1 {
+                writeln!(out, "Yearly Returns:")?;
+                for (year, yearly_return) in &result.yearly_returns {
+                    writeln!(out, "  {}: {}", year, options.gain(options.percent(yearly_return * Decimal::from(100)), *yearly_return))?;
+                }
+            }
+            if let Some(as_of) = &result.as_of {
+                writeln!(out, "As of {} (week {}): holding {}, value {}, cost basis {}, unrealized gain {}",
+                    as_of.date, as_of.week, as_of.holding,
+                    options.money(as_of.market_value),
+                    options.money(as_of.cost_basis),
+                    options.money(as_of.unrealized_gain))?;
+            }
+            if !result.ending_positions.is_empty() {
+                writeln!(out, "Ending Positions:")?;
+                for position in &result.ending_positions {
+                    writeln!(out, "  {} ({}): {} shares, value {}, contribution {}",
+                        position.company, position.ticker, options.shares(position.shares),
+                        options.money(position.value), options.money(position.contribution))?;
+                }
+            }
+            writeln!(out, "Number of Trades: {}", result.trades.len())?;
+            let total_fees: Decimal = result.trades.iter().map(|trade| trade.fee).sum();
+            if total_fees > Decimal::ZERO {
+                writeln!(out, "Total Fees: {}", options.money(total_fees))?;
+            }
+            
+            if !result.trades.is_empty() && !options.quiet {
+                writeln!(out, "Sample Trades:")?;
+                for trade in result.trades.iter().take(5) {
+                    writeln!(out, "  Week {}: {} @ {} ({} shares, {} invested)",
+                        trade.week, trade.company, options.money(trade.price),
+                        options.shares(trade.shares_bought), options.money(trade.amount_invested))?;
+                }
+                if result.trades.len() > 5 {
+                    writeln!(out, "  ... and {} more trades", result.trades.len() - 5)?;
+                }
+            }
+            if options.quiet {
+                writeln!(out)?;
+            } else {
+                writeln!(out, "{}", "-".repeat(50))?;
+            }
+        }
+
+        Self::write_summary(out, results, options)
+    }
+
+    /// Distinct patterns, in result order, whose percentage gain is within
+    /// `epsilon` percentage points of `gain`
+    pub fn tied_patterns(results: &[SimulationResult], gain: Decimal, epsilon: Decimal) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for result in results {
+            if (result.percentage_gain - gain).abs() <= epsilon && !names.contains(&result.pattern_name.as_str()) {
+                names.push(&result.pattern_name);
+            }
+        }
+        names
+    }
+
+    /// Best, worst and typical gain across all results
+    fn write_summary(out: &mut impl Write, results: &[SimulationResult], options: &OutputOptions) -> std::io::Result<()> {
+        if !results.is_empty() {
+            let best_result = results.iter()
+                .max_by(|a, b| a.percentage_gain.partial_cmp(&b.percentage_gain).unwrap())
+                .unwrap();
+            
+            let worst_result = results.iter()
+                .min_by(|a, b| a.percentage_gain.partial_cmp(&b.percentage_gain).unwrap())
+                .unwrap();
+
+            if let Some(banner) = options.banner_text("SUMMARY") {
+                writeln!(out, "{}", banner)?;
+            }
+            writeln!(out, "Best Performance: {} with {} gain",
+                Self::tied_patterns(results, best_result.percentage_gain, options.tie_epsilon).join(", "),
+                options.gain(options.percent(best_result.percentage_gain), best_result.percentage_gain))?;
+            writeln!(out, "Worst Performance: {} with {} gain",
+                Self::tied_patterns(results, worst_result.percentage_gain, options.tie_epsilon).join(", "),
+                options.gain(options.percent(worst_result.percentage_gain), worst_result.percentage_gain))?;
+
+            let mut gains: Vec<Decimal> = results.iter().map(|r| r.percentage_gain).collect();
+            gains.sort();
+            let mean = gains.iter().sum::<Decimal>() / Decimal::from(gains.len());
+            writeln!(out, "Mean Gain: {} (median {}) across {} simulations",
+                options.gain(options.percent(mean), mean),
+                options.percent(metrics::median(&gains)),
+                gains.len())?;
+        }
+        Ok(())
+    }
+
+    /// Sorts results by the given dimensions, outermost first. Values within a
+    /// dimension keep the order they first appear in, which is the DSL's order.
+    pub fn order_results(results: &mut [SimulationResult], order: &ResultOrder) {
+        let rank = |values: Vec<String>| -> HashMap<String, usize> {
+            let mut ranks = HashMap::new();
+            for value in values {
+                let next = ranks.len();
+                ranks.entry(value).or_insert(next);
+            }
+            ranks
+        };
+        let patterns = rank(results.iter().map(|r| r.pattern_name.clone()).collect());
+        let amounts = rank(results.iter().map(|r| r.initial_amount.to_string()).collect());
+        let frames = rank(results.iter().map(|r| r.time_frame.to_string()).collect());
+
+        results.sort_by_cached_key(|result| {
+            order.0.iter().map(|dimension| match dimension {
+                Dimension::Pattern => patterns[&result.pattern_name],
+                Dimension::Amount => amounts[&result.initial_amount.to_string()],
+                Dimension::Frame => frames[&result.time_frame.to_string()],
+            }).collect::<Vec<usize>>()
+        });
+    }
+
+    /// Pivots results so each pattern and time frame is a row with one
+    /// percentage-gain column per invest amount
+    pub fn group_by_amount(results: &[SimulationResult]) -> AmountTable {
+        let mut amounts: Vec<Decimal> = results.iter().map(|r| r.initial_amount).collect();
+        amounts.sort();
+        amounts.dedup();
+
+        let mut rows: Vec<AmountRow> = Vec::new();
+        for result in results {
+            let time_frame = result.time_frame.to_string();
+            let index = match rows.iter().position(|row| {
+                row.pattern_name == result.pattern_name && row.time_frame == time_frame
+            }) {
+                Some(index) => index,
+                None => {
+                    rows.push(AmountRow {
+                        pattern_name: result.pattern_name.clone(),
+                        time_frame,
+                        gains: vec![None; amounts.len()],
+                    });
+                    rows.len() - 1
+                }
+            };
+            let column = amounts.iter().position(|a| *a == result.initial_amount).unwrap_or_default();
+            rows[index].gains[column] = Some(result.percentage_gain);
+        }
+
+        AmountTable { amounts, rows }
+    }
+
+    pub fn print_amount_table(table: &AmountTable, options: &OutputOptions) {
+        options.banner("GAIN BY INVEST AMOUNT");
+        println!();
+
+        let headers: Vec<String> = table.amounts.iter().map(|a| options.money(*a)).collect();
+        let cells: Vec<Vec<String>> = table.rows.iter().map(|row| {
+            row.gains.iter().map(|gain| match gain {
+                Some(gain) => options.percent(*gain),
+                None => "n/a".to_string(),
+            }).collect()
+        }).collect();
+
+        let label_width = table.rows.iter()
+            .map(|row| row.pattern_name.len() + row.time_frame.len() + 1)
+            .max()
+            .unwrap_or(0)
+            .max(7);
+        let width = headers.iter().chain(cells.iter().flatten()).map(|c| c.len()).max().unwrap_or(0);
+
+        print!("{:label_width$}", "Pattern", label_width = label_width);
+        for header in &headers {
+            print!("  {:>width$}", header, width = width);
+        }
+        println!();
+
+        for (row, cells) in table.rows.iter().zip(&cells) {
+            print!("{:label_width$}", format!("{} {}", row.pattern_name, row.time_frame), label_width = label_width);
+            for cell in cells {
+                print!("  {:>width$}", cell, width = width);
+            }
+            println!();
+        }
+    }
+
+    pub fn print_fee_comparison(comparisons: &[FeeComparison], options: &OutputOptions) {
+        if let Err(e) = Self::write_fee_comparison(&mut std::io::stdout().lock(), comparisons, options) {
+            eprintln!("Error writing fee comparison: {}", e);
+        }
+    }
+
+    /// Writes each simulation's total fees and net final amount per fee model to `out`
+    pub fn write_fee_comparison(out: &mut impl Write, comparisons: &[FeeComparison], options: &OutputOptions) -> std::io::Result<()> {
+        if let Some(banner) = options.banner_text("FEE MODEL COMPARISON") {
+            writeln!(out, "{}", banner)?;
+        }
+
+        let rows: Vec<[String; 4]> = comparisons.iter().map(|comparison| [
+            format!("{} {} {}", comparison.pattern_name, options.money(comparison.initial_amount), comparison.time_frame),
+            comparison.fee_model.clone(),
+            options.money(comparison.total_fees),
+            options.money(comparison.final_amount),
+        ]).collect();
+        let headers = ["Simulation", "Fee model", "Total fees", "Net final"];
+        let widths: Vec<usize> = (0..headers.len())
+            .map(|column| rows.iter().map(|row| row[column].len()).chain([headers[column].len()]).max().unwrap_or(0))
+            .collect();
+
+        writeln!(out, "{:w0$}  {:w1$}  {:>w2$}  {:>w3$}",
+            headers[0], headers[1], headers[2], headers[3], w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3])?;
+        for row in &rows {
+            writeln!(out, "{:w0$}  {:w1$}  {:>w2$}  {:>w3$}",
+                row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3])?;
+        }
+        Ok(())
+    }
+
+    pub fn print_provenance(provenance: &[PriceProvenance], options: &OutputOptions) {
+        if let Err(e) = Self::write_provenance(&mut std::io::stdout().lock(), provenance, options) {
+            eprintln!("Error writing provenance: {}", e);
+        }
+    }
+
+    /// Writes each ticker's entry price source and history span to `out`
+    pub fn write_provenance(out: &mut impl Write, provenance: &[PriceProvenance], options: &OutputOptions) -> std::io::Result<()> {
+        if let Some(banner) = options.banner_text("PRICE PROVENANCE") {
+            writeln!(out, "{}", banner)?;
+        }
+
+        for entry in provenance {
+            writeln!(out, "{}: entry {} from the {}", entry.ticker, options.money(entry.price), entry.source)?;
+            match (entry.first_close, entry.last_close) {
+                (Some((first_date, first_close)), Some((last_date, last_close))) => {
+                    writeln!(out, "  Returns from {} daily bars: {} close {} to {} close {}",
+                        entry.bars, first_date, options.money(first_close), last_date, options.money(last_close))?;
+                }
+                _ => writeln!(out, "  No price history; annual return is zero")?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print_correlations(matrices: &[CorrelationMatrix], options: &OutputOptions) {
+        options.banner("CORRELATIONS (weekly returns)");
+
+        for matrix in matrices {
+            println!("\nPattern: {}", matrix.pattern_name);
+            let width = matrix.tickers.iter().map(|t| t.len()).max().unwrap_or(0).max(6);
+
+            print!("{:width$}", "", width = width);
+            for ticker in &matrix.tickers {
+                print!("  {:>width$}", ticker, width = width);
+            }
+            println!();
+
+            for (ticker, row) in matrix.tickers.iter().zip(&matrix.values) {
+                print!("{:width$}", ticker, width = width);
+                for value in row {
+                    let cell = match value {
+                        Some(correlation) => options.ratio(*correlation),
+                        None => "n/a".to_string(),
+                    };
+                    print!("  {:>width$}", cell, width = width);
+                }
+                println!();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::testing::{series, HistoryProvider};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn prices(entries: &[(&str, i64)]) -> HashMap<String, Decimal> {
+        entries.iter().map(|(ticker, price)| (ticker.to_string(), Decimal::from(*price))).collect()
+    }
+
+    async fn simulate(source: &str, provider: impl StockDataProvider + 'static) -> Vec<SimulationResult> {
+        let dsl = StockDSL::parse(source).unwrap();
+        Simulator::with_provider(provider).run_simulations(&dsl).await.unwrap()
+    }
+
+    #[test]
+    fn as_of_marks_to_market_at_the_week_containing_the_date() {
+        let simulator = Simulator::with_prices(HashMap::new()).with_start_date(date(2023, 1, 2));
+        let curve: Vec<Decimal> = (0..=52).map(|week| Decimal::from(1000 + 10 * week)).collect();
+        let pattern = vec!["Apple".to_string(), "Microsoft".to_string()];
+
+        // 179 days in: 25 full weeks have elapsed, so week 26 is under way
+        let valuation = simulator.value_as_of(date(2023, 6, 30), &pattern, &curve, Decimal::from(1000)).unwrap();
+        assert_eq!(valuation.week, 26);
+        assert_eq!(valuation.market_value, Decimal::from(1250));
+        assert_eq!(valuation.unrealized_gain, Decimal::from(250));
+        assert_eq!(valuation.holding, "Microsoft");
+
+        assert!(simulator.value_as_of(date(2024, 6, 30), &pattern, &curve, Decimal::from(1000)).is_none());
+    }
+
+    #[tokio::test]
+    async fn start_date_anchors_trades_and_as_of() {
+        let dsl = StockDSL::parse("INVEST 1000\nTIME 1y\nINVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p").unwrap();
+        let mut simulator = Simulator::with_prices(prices(&[("AAPL", 100)]))
+            .with_start_date(date(2023, 1, 2))
+            .with_as_of(date(2023, 6, 30));
+        let results = simulator.run_simulations(&dsl).await.unwrap();
+        assert_eq!(results[0].trades[0].date, date(2023, 1, 2));
+        assert_eq!(results[0].as_of.as_ref().unwrap().week, 26);
+
+        let mut early = Simulator::with_prices(prices(&[("AAPL", 100)]))
+            .with_start_date(date(2023, 1, 2))
+            .with_as_of(date(2022, 12, 31));
+        assert!(early.run_simulations(&dsl).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fixed_start_date_makes_date_dependent_output_repeatable() {
+        let dsl = StockDSL::parse("INVEST 1000\nTIME 1y\nINVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p").unwrap();
+        let run = || async {
+            Simulator::with_prices(prices(&[("AAPL", 100)]))
+                .with_start_date(date(2023, 7, 3))
+                .run_simulations(&dsl)
+                .await
+                .unwrap()
+        };
+        let (first, second) = (run().await, run().await);
+
+        let years: Vec<i32> = first[0].yearly_returns.iter().map(|(year, _)| *year).collect();
+        assert_eq!(years, vec![2023, 2024]);
+        assert_eq!(first[0].snapshots.last().unwrap().date, date(2024, 7, 1));
+        assert_eq!(Simulator::fingerprint(&first), Simulator::fingerprint(&second));
+    }
+
+    #[tokio::test]
+    async fn snapshots_cover_every_week_and_sum_to_the_portfolio() {
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nTIME 10w\nCASH_BUFFER 10%\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN p Apple, Microsoft\nTEST p",
+        ).unwrap();
+        let results = Simulator::with_prices(prices(&[("AAPL", 100), ("MSFT", 400)]))
+            .run_simulations(&dsl)
+            .await
+            .unwrap();
+
+        let snapshots = &results[0].snapshots;
+        assert_eq!(snapshots.len(), 10);
+        assert_eq!(snapshots.last().unwrap().total_value, results[0].final_amount);
+        for snapshot in snapshots {
+            let held: Decimal = snapshot.holdings.iter().map(|h| h.value).sum();
+            assert_eq!(snapshot.cash + held, snapshot.total_value);
+            assert!(snapshot.cash > Decimal::ZERO);
+        }
+
+        let path = std::env::temp_dir().join(format!("snapshots-{}.json", std::process::id()));
+        Simulator::write_snapshots(&results, &path).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written[0]["snapshots"].as_array().unwrap().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn prices_file_seeds_tickers_without_fetching() {
+        let path = std::env::temp_dir().join(format!("prices-{}.csv", std::process::id()));
+        std::fs::write(&path, "ticker,price\nAAPL,150\n# comment\nMSFT, 400.5\n").unwrap();
+        assert_eq!(
+            read_prices_file(&path).unwrap(),
+            vec![("AAPL".to_string(), Decimal::from(150)), ("MSFT".to_string(), Decimal::new(4005, 1))],
+        );
+
+        // An empty fixed-price provider errors on any symbol the file doesn't seed
+        let dsl = StockDSL::parse(&format!(
+            "PRICES \"{}\"\nINVEST 1000\nTIME 4w\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN p Apple, Microsoft\nTEST p",
+            path.display(),
+        )).unwrap();
+        let results = Simulator::with_provider(FixedPriceProvider::default()).run_simulations(&dsl).await;
+        let mut unseeded = dsl.clone();
+        unseeded.prices_file = None;
+        std::fs::remove_file(&path).unwrap();
+
+        let results = results.unwrap();
+        assert_eq!(results[0].final_amount, Decimal::from(1000));
+        assert_eq!(results[0].trades[0].price, Decimal::from(150));
+        assert!(Simulator::with_provider(FixedPriceProvider::default()).run_simulations(&unseeded).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ending_positions_split_the_final_amount_by_pattern_weight() {
+        let provider = HistoryProvider::new([
+            series("AAPL", &[100, 105, 110, 115, 120]),
+            series("MSFT", &[400, 410, 420, 430, 440]),
+        ]);
+        let results = simulate(
+            "INVEST 900\nTIME 8w\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN p Apple, Apple, Microsoft\nTEST p",
+            provider,
+        ).await;
+        let result = &results[0];
+        assert!(result.total_gain > Decimal::ZERO);
+
+        let positions = &result.ending_positions;
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].ticker, "AAPL");
+        assert_eq!((positions[0].value / positions[1].value).round_dp(10), Decimal::from(2));
+
+        let value: Decimal = positions.iter().map(|p| p.value).sum();
+        let contribution: Decimal = positions.iter().map(|p| p.contribution).sum();
+        assert_eq!(value.round_dp(10), result.final_amount.round_dp(10));
+        assert_eq!(contribution.round_dp(10), result.total_gain.round_dp(10));
+    }
+
+    #[tokio::test]
+    async fn max_trades_caps_a_spread_initial_deployment() {
+        let source = |max_trades: u32, spread: &str| format!(
+            "INVEST 1000\nTIME 8w\nMAX_TRADES {}\nINITIAL_DEPLOY spread {}\nINVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p",
+            max_trades, spread,
+        );
+        let run = |source: String| async move {
+            let dsl = StockDSL::parse(&source).unwrap();
+            Simulator::with_prices(prices(&[("AAPL", 100)])).run_simulations(&dsl).await
+        };
+
+        let results = run(source(3, "3w")).await.unwrap();
+        assert_eq!(results[0].trades.len(), 3);
+
+        let error = run(source(3, "4w")).await.unwrap_err().to_string();
+        assert!(error.contains("exceeded MAX_TRADES of 3 at week 4"), "{}", error);
+
+        assert!(StockDSL::parse("MAX_TRADES 0").is_err());
+    }
+
+    #[tokio::test]
+    async fn mixed_currencies_are_rejected_wherever_the_ticker_appears() {
+        let provider = || {
+            let mut sap = series("SAP", &[100, 110]);
+            sap.currency = Some("EUR".to_string());
+            let mut apple = series("AAPL", &[100, 110]);
+            apple.currency = Some("USD".to_string());
+            HistoryProvider::new([apple, sap])
+        };
+        let run = |source: &str, holdings: Option<InitialHoldings>| {
+            let dsl = StockDSL::parse(source).unwrap();
+            let mut simulator = Simulator::with_provider(provider()).with_base_currency("USD".to_string());
+            if let Some(holdings) = holdings {
+                simulator = simulator.with_initial_holdings(holdings);
+            }
+            async move { simulator.run_simulations(&dsl).await.map_err(|e| e.to_string()) }
+        };
+        let apple_only = "INVEST 1000\nTIME 4w\nINVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p";
+
+        assert!(run(apple_only, None).await.is_ok());
+
+        let error = run(&format!("{}\nINVESTMENT SAP Sap", apple_only), None).await.unwrap_err();
+        assert_eq!(error, "No FX rate available to convert into USD: SAP (EUR)");
+
+        let error = run(&format!("{}\nBENCHMARK SAP", apple_only), None).await.unwrap_err();
+        assert!(error.contains("SAP (EUR)"), "{}", error);
+
+        let holdings = InitialHoldings { cash: Decimal::ZERO, shares: HashMap::from([("SAP".to_string(), Decimal::ONE)]) };
+        let error = run(apple_only, Some(holdings)).await.unwrap_err();
+        assert!(error.contains("SAP (EUR)"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn simulate_pattern_runs_offline_on_fixed_prices() {
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nTIME 4w\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN p Apple, Microsoft\nTEST p",
+        ).unwrap();
+        let mut simulator = Simulator::with_prices(prices(&[("AAPL", 200), ("MSFT", 400)]));
+        let time_frame = dsl.time_frames[0].clone();
+        let pattern = dsl.patterns["p"].clone();
+
+        let result = simulator.simulate_pattern("p", &pattern, Decimal::from(1000), &time_frame, &dsl).await.unwrap();
+        assert_eq!(result.final_amount, Decimal::from(1000));
+        assert_eq!(result.total_gain, Decimal::ZERO);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].ticker, "AAPL");
+        assert_eq!(result.trades[0].shares_bought, Decimal::from(5));
+
+        let mut simulator = Simulator::with_prices(prices(&[("AAPL", 200)]));
+        assert!(simulator.simulate_pattern("p", &pattern, Decimal::from(1000), &time_frame, &dsl).await.is_err());
+    }
+
+    /// A year of weekly closes rising evenly from 100 to `end`
+    fn year_of(symbol: &str, end: i64) -> crate::yahoo_finance::StockData {
+        let closes: Vec<i64> = (0..=52).map(|week| 100 + (end - 100) * week / 52).collect();
+        series(symbol, &closes)
+    }
+
+    #[tokio::test]
+    async fn blended_benchmark_matches_a_pattern_holding_the_same_blend() {
+        let source = |benchmark: &str| format!(
+            "INVEST 1000\nTIME 1y\nINVESTMENT SPY Stocks\nINVESTMENT AGG Bonds\nPATTERN p Stocks, Stocks, Stocks, Bonds, Bonds\nBENCHMARK {}\nTEST p",
+            benchmark,
+        );
+        let provider = || HistoryProvider::new([year_of("SPY", 120), year_of("AGG", 104)]);
+
+        let blended = &simulate(&source("0.6 SPY, 0.4 AGG"), provider()).await[0];
+        assert_eq!(
+            blended.benchmark_percentage_gain.unwrap().round_dp(6),
+            blended.percentage_gain.round_dp(6),
+        );
+
+        let stocks = simulate(&source("SPY"), provider()).await[0].benchmark_percentage_gain.unwrap();
+        let bonds = simulate(&source("AGG"), provider()).await[0].benchmark_percentage_gain.unwrap();
+        assert!(bonds < blended.percentage_gain && blended.percentage_gain < stocks);
+    }
+
+    #[tokio::test]
+    async fn vol_target_scales_down_only_the_volatile_pattern() {
+        let swinging: Vec<i64> = (0..=26).map(|week| if week % 2 == 0 { 100 } else { 130 }).collect();
+        let steady: Vec<i64> = (0..=26).map(|week| 100 + week).collect();
+        let results = simulate(
+            "INVEST 1000\nTIME 8w\nVOL_TARGET 10%\nINVESTMENT SWNG Swing\nINVESTMENT STDY Steady\nPATTERN wild Swing\nPATTERN calm Steady\nTEST wild\nTEST calm",
+            HistoryProvider::new([series("SWNG", &swinging), series("STDY", &steady)]),
+        ).await;
+
+        let exposure = |name: &str| results.iter().find(|r| r.pattern_name == name).unwrap().vol_target_exposure.unwrap();
+        assert!(exposure("wild") < Decimal::new(1, 1), "{}", exposure("wild"));
+        assert_eq!(exposure("calm"), Decimal::ONE);
+    }
+
+    #[tokio::test]
+    async fn spread_deployment_records_one_tranche_per_week() {
+        let run = |time: &str, spread: &str| {
+            let dsl = StockDSL::parse(&format!(
+                "INVEST 1000\nTIME {}\nINITIAL_DEPLOY spread {}\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN p Apple, Microsoft\nTEST p",
+                time, spread,
+            )).unwrap();
+            let mut simulator = Simulator::with_prices(prices(&[("AAPL", 100), ("MSFT", 250)])).with_start_date(date(2024, 1, 1));
+            async move { simulator.run_simulations(&dsl).await.unwrap().remove(0) }
+        };
+
+        let trades = run("10w", "4w").await.trades;
+        assert_eq!(trades.len(), 4);
+        assert_eq!(trades.iter().map(|t| t.ticker.as_str()).collect::<Vec<_>>(), ["AAPL", "MSFT", "AAPL", "MSFT"]);
+        assert!(trades.iter().all(|t| t.amount_invested == Decimal::from(250)));
+        assert_eq!(trades[1].shares_bought, Decimal::ONE);
+        assert_eq!(trades[3].date, date(2024, 1, 22));
+
+        // Never spread past the horizon
+        assert_eq!(run("3w", "20w").await.trades.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn oneline_prints_plain_key_value_tokens() {
+        let dsl = StockDSL::parse(
+            "INVEST 12345.678\nTIME 2w\nINVESTMENT AAPL Apple\nPATTERN growth Apple\nTEST growth",
+        ).unwrap();
+        let results = Simulator::with_prices(prices(&[("AAPL", 100)])).run_simulations(&dsl).await.unwrap();
+        // Unlocalized even under a grouping locale
+        let options = OutputOptions { locale: format::Locale::DeDe, ..OutputOptions::default() };
+
+        assert_eq!(
+            Simulator::oneline(&results[0], &options),
+            "result pattern=growth invest=12345.68 frame=2w final=12345.68 gain=0.00% dd=0.00%",
+        );
+    }
+
+    #[tokio::test]
+    async fn broker_csv_lists_every_trade_in_broker_columns() {
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nTIME 4w\nINITIAL_DEPLOY spread 2w\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN p Apple, Microsoft\nTEST p",
+        ).unwrap();
+        let mut results = Simulator::with_prices(prices(&[("AAPL", 200), ("MSFT", 400)]))
+            .with_start_date(date(2024, 1, 1))
+            .run_simulations(&dsl)
+            .await
+            .unwrap();
+        let options = OutputOptions { locale: format::Locale::DeDe, ..OutputOptions::default() };
+
+        assert_eq!(
+            Simulator::broker_csv(&results, &options),
+            "run,date,symbol,action,quantity,price,fee,amount\n\
+             p/1000.00/4w,2024-01-01,AAPL,BUY,2.5000,200.00,0.00,500.00\n\
+             p/1000.00/4w,2024-01-08,MSFT,BUY,1.2500,400.00,0.00,500.00\n",
+        );
+
+        // Giving shares up is a sale, with the quantity and amount unsigned
+        let mut sale = results[0].trades[1].clone();
+        sale.shares_bought = -sale.shares_bought;
+        sale.amount_invested = -sale.amount_invested;
+        assert_eq!(sale.action(), "SELL");
+        results[0].trades = vec![sale];
+        assert_eq!(
+            Simulator::broker_csv(&results, &options),
+            "run,date,symbol,action,quantity,price,fee,amount\n\
+             p/1000.00/4w,2024-01-08,MSFT,SELL,1.2500,400.00,0.00,500.00\n",
+        );
+    }
+
+    async fn two_pattern_results() -> Vec<SimulationResult> {
+        let provider = HistoryProvider::new([year_of("UP", 120), year_of("FLAT", 100)]);
+        simulate(
+            "INVEST 1000\nTIME 4w\nINVESTMENT UP Up\nINVESTMENT FLAT Flat\nPATTERN rising Up\nPATTERN idle Flat\nTEST rising\nTEST idle",
+            provider,
+        ).await
+    }
+
+    fn written(results: &[SimulationResult], options: &OutputOptions) -> String {
+        let mut out = Vec::new();
+        Simulator::write_results(&mut out, results, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn summary_only_skips_the_per_result_reports() {
+        let results = two_pattern_results().await;
+        let options = OutputOptions { summary_only: true, ..OutputOptions::default() };
+
+        let output = written(&results, &options);
+        assert!(output.starts_with("\n=== SUMMARY ===\nBest Performance: rising with "), "{}", output);
+        assert!(output.contains("Worst Performance: idle with 0.00% gain\n"));
+        assert!(output.contains("across 2 simulations\n"));
+        assert!(!output.contains("Pattern: "));
+        assert!(!output.contains("STOCK SIMULATION RESULTS"));
+    }
+
+    #[tokio::test]
+    async fn results_within_epsilon_share_best_performance() {
+        let mut results = two_pattern_results().await;
+        results[0].percentage_gain = Decimal::new(1000005, 5);
+        results[1].percentage_gain = Decimal::from(10);
+        let mut third = results[1].clone();
+        third.pattern_name = "laggard".to_string();
+        third.percentage_gain = Decimal::from(5);
+        results.push(third);
+
+        let best = results[0].percentage_gain;
+        assert_eq!(Simulator::tied_patterns(&results, best, Decimal::new(1, 4)), ["rising", "idle"]);
+        assert_eq!(Simulator::tied_patterns(&results, best, Decimal::new(1, 5)), ["rising"]);
+
+        let output = written(&results, &OutputOptions { summary_only: true, ..OutputOptions::default() });
+        assert!(output.contains("Best Performance: rising, idle with 10.00% gain\n"), "{}", output);
+        assert!(output.contains("Worst Performance: laggard with 5.00% gain\n"), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn amount_table_has_one_column_per_invest_amount() {
+        let provider = HistoryProvider::new([year_of("UP", 120)]);
+        let mut results = simulate(
+            "INVEST 5000, 1000\nTIME 4w, 8w\nINVESTMENT UP Up\nPATTERN rising Up\nTEST rising",
+            provider,
+        ).await;
+        // A combination that wasn't run leaves its cell empty
+        results.retain(|r| !(r.initial_amount == Decimal::from(5000) && r.time_frame.to_string() == "8w"));
+
+        let table = Simulator::group_by_amount(&results);
+        assert_eq!(table.amounts, [Decimal::from(1000), Decimal::from(5000)]);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!((table.rows[0].pattern_name.as_str(), table.rows[0].time_frame.as_str()), ("rising", "4w"));
+        assert!(table.rows[0].gains.iter().all(Option::is_some));
+        assert_eq!(table.rows[1].time_frame, "8w");
+        assert!(table.rows[1].gains[0].is_some() && table.rows[1].gains[1].is_none());
+    }
+
+    #[tokio::test]
+    async fn quiet_output_keeps_metrics_but_drops_banners() {
+        let results = two_pattern_results().await;
+        let loud = written(&results, &OutputOptions::default());
+        let quiet = written(&results, &OutputOptions { quiet: true, ..OutputOptions::default() });
+
+        assert!(loud.contains("=== STOCK SIMULATION RESULTS ===") && loud.contains("=== SUMMARY ==="));
+        assert!(loud.contains("Sample Trades:") && loud.contains(&"-".repeat(50)));
+        assert!(!quiet.contains("===") && !quiet.contains("Sample Trades:") && !quiet.contains(&"-".repeat(50)));
+        for line in ["Pattern: rising", "Final Amount: ", "Best Performance: rising"] {
+            assert!(quiet.contains(line), "missing {:?} in {}", line, quiet);
+        }
+        assert!(quiet.starts_with("Pattern: rising\n"));
+    }
+
+    #[tokio::test]
+    async fn fingerprint_ignores_result_order_and_start_date_but_not_inputs() {
+        let run = |invest: u32, start: NaiveDate| {
+            let dsl = StockDSL::parse(&format!(
+                "INVEST {}\nTIME 10w\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nPATTERN a Apple\nPATTERN m Microsoft\nTEST a\nTEST m",
+                invest,
+            )).unwrap();
+            let mut simulator = Simulator::with_prices(prices(&[("AAPL", 100), ("MSFT", 400)])).with_start_date(start);
+            async move { simulator.run_simulations(&dsl).await.unwrap() }
+        };
+
+        let results = run(1000, date(2024, 1, 1)).await;
+        let fingerprint = Simulator::fingerprint(&results);
+        assert_eq!(fingerprint.len(), 16);
+
+        let mut reordered = results.clone();
+        reordered.reverse();
+        assert_eq!(Simulator::fingerprint(&reordered), fingerprint);
+
+        assert_eq!(Simulator::fingerprint(&run(1000, date(2025, 6, 16)).await), fingerprint);
+        assert_ne!(Simulator::fingerprint(&run(2000, date(2024, 1, 1)).await), fingerprint);
+    }
+
+    #[tokio::test]
+    async fn withdrawals_report_the_week_they_empty_the_portfolio() {
+        let run = |withdraw: &str| {
+            let dsl = StockDSL::parse(&format!(
+                "INVEST 1000\nTIME 10w\nWITHDRAW {}\nINVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p",
+                withdraw,
+            )).unwrap();
+            async move { Simulator::with_prices(prices(&[("AAPL", 100)])).run_simulations(&dsl).await.unwrap().remove(0) }
+        };
+
+        // 300 a week leaves 100 for the fourth withdrawal
+        let depleted = run("300 weekly").await;
+        assert_eq!(depleted.depleted_week, Some(4));
+        assert_eq!(depleted.withdrawn, Decimal::from(1000));
+        assert_eq!(depleted.final_amount, Decimal::ZERO);
+
+        let sustained = run("50 weekly").await;
+        assert_eq!(sustained.depleted_week, None);
+        assert_eq!(sustained.withdrawn, Decimal::from(500));
+        assert_eq!(sustained.final_amount, Decimal::from(500));
+    }
+
+    #[tokio::test]
+    async fn an_interrupted_sweep_resumes_from_its_checkpoint() {
+        let path = std::env::temp_dir().join(format!("sweep-{}.ckpt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sweep = |price: i64, max_trades: &str, start: NaiveDate| {
+            let dsl = StockDSL::parse(&format!(
+                "INVEST 1000\nTIME 2w, 8w\nINITIAL_DEPLOY spread 4w\n{}\nINVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p",
+                max_trades,
+            )).unwrap();
+            let mut simulator = Simulator::with_prices(prices(&[("AAPL", price)]))
+                .with_start_date(start)
+                .with_checkpoint(path.clone(), "dsl".to_string());
+            async move { simulator.run_simulations(&dsl).await }
+        };
+
+        // The 8w frame trips MAX_TRADES after 2w has been saved
+        assert!(sweep(100, "MAX_TRADES 3", date(2024, 1, 1)).await.is_err());
+
+        // The rerun keeps the saved 2w result, priced at 100, and runs only 8w
+        let resumed = sweep(200, "", date(2024, 1, 1)).await.unwrap();
+        assert_eq!(resumed[0].trades[0].price, Decimal::from(100));
+        assert_eq!(resumed[1].trades[0].price, Decimal::from(200));
+
+        // A different start date changes the results, so nothing is reused
+        let restarted = sweep(300, "", date(2024, 2, 5)).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(restarted.iter().all(|r| r.trades[0].price == Decimal::from(300)));
+    }
+
+    #[tokio::test]
+    async fn initial_holdings_are_marked_to_market_in_week_one() {
+        let path = std::env::temp_dir().join(format!("holdings-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"cash": 250, "shares": {"MSFT": 2}}"#).unwrap();
+        let holdings = InitialHoldings::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let dsl = StockDSL::parse("INVEST 0\nTIME 4w\nINVESTMENT AAPL Apple\nPATTERN p Apple\nTEST p").unwrap();
+        let results = Simulator::with_prices(prices(&[("AAPL", 100), ("MSFT", 400)]))
+            .with_initial_holdings(holdings.unwrap())
+            .run_simulations(&dsl)
+            .await
+            .unwrap();
+
+        // 2 MSFT at 400 plus 250 cash
+        assert_eq!(results[0].initial_holdings_value, Decimal::from(1050));
+        assert_eq!(results[0].snapshots[0].total_value, Decimal::from(1050));
+        assert_eq!(results[0].snapshots[0].cash, Decimal::from(250));
+    }
+
+    #[tokio::test]
+    async fn describe_text_reaches_the_report_and_json_export() {
+        let provider = HistoryProvider::new([year_of("UP", 120), year_of("FLAT", 100)]);
+        let results = simulate(
+            "INVEST 1000\nTIME 4w\nINVESTMENT UP Up\nINVESTMENT FLAT Flat\nPATTERN rising Up\nPATTERN idle Flat\n\
+             DESCRIBE rising \"Ride the uptrend\"\nTEST rising\nTEST idle",
+            provider,
+        ).await;
+        assert_eq!(results[0].description.as_deref(), Some("Ride the uptrend"));
+        assert_eq!(results[1].description, None);
+
+        let output = written(&results, &OutputOptions::default());
+        assert!(output.contains("Pattern: rising\nDescription: Ride the uptrend\n"), "{}", output);
+        assert_eq!(output.matches("Description: ").count(), 1);
+
+        let path = std::env::temp_dir().join(format!("snapshots-{}.json", std::process::id()));
+        Simulator::write_snapshots(&results, &path).unwrap();
+        let export: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(export[0]["pattern"], "rising");
+        assert_eq!(export[0]["description"], "Ride the uptrend");
+        assert!(export[1].get("description").is_none());
+    }
+
+    /// One pattern holding a ticker that hits the 40% annual return cap
+    async fn capped_year(extra: &str) -> SimulationResult {
+        let source = format!("INVEST 1000\nTIME 1y\nINVESTMENT UP Up\nPATTERN p Up\nTEST p\n{}", extra);
+        simulate(&source, HistoryProvider::new([year_of("UP", 150)])).await.remove(0)
+    }
+
+    #[tokio::test]
+    async fn fees_come_out_before_growth() {
+        assert_eq!(capped_year("").await.final_amount, Decimal::from(1400));
+
+        // 10% of 1000 is charged up front, so only 900 compounds at 40%
+        let percentage = capped_year("FEE 10%").await;
+        assert_eq!(percentage.trades[0].fee, Decimal::from(100));
+        assert_eq!(percentage.trades[0].amount_invested, Decimal::from(1000));
+        assert_eq!(percentage.trades[0].shares_bought, Decimal::from(900) / Decimal::from(150));
+        assert_eq!(percentage.final_amount, Decimal::from(1260));
+
+        let flat = capped_year("FEE 4.95").await;
+        assert_eq!(flat.trades[0].fee, Decimal::new(495, 2));
+        assert_eq!(flat.final_amount, Decimal::new(139307, 2));
+    }
+
+    #[tokio::test]
+    async fn fees_apply_to_every_tranche() {
+        let free = capped_year("INITIAL_DEPLOY spread 4w").await;
+        let charged = capped_year("INITIAL_DEPLOY spread 4w\nFEE 5").await;
+        assert_eq!(charged.trades.len(), 4);
+        assert!(charged.trades.iter().all(|trade| trade.fee == Decimal::from(5)));
+
+        // Four 5.00 fees, each losing the growth left after its own week
+        let cost = free.final_amount - charged.final_amount;
+        assert!(cost > Decimal::from(27) && cost < Decimal::from(28), "{}", cost);
+    }
+
+    #[tokio::test]
+    async fn provenance_tells_fetched_bars_from_fixed_prices() {
+        let mut fetched = series("AAPL", &[100, 110, 120]);
+        fetched.fixed = false;
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nTIME 4w\nENTRY_PRICE open\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\nINVESTMENT NEW Newco\n\
+             PATTERN p Apple, Microsoft, Newco\nTEST p",
+        ).unwrap();
+        let mut simulator = Simulator::with_provider(HistoryProvider::new([
+            fetched,
+            series("MSFT", &[400, 410]),
+            crate::yahoo_finance::StockData::fixed("NEW", Decimal::from(10)),
+        ]));
+
+        let provenance = simulator.price_provenance(&dsl).await.unwrap();
+        let mut out = Vec::new();
+        Simulator::write_provenance(&mut out, &provenance, &OutputOptions::default()).unwrap();
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("AAPL: entry $120.00 from the open of the 2024-01-15 bar\n"), "{}", output);
+        assert!(output.contains("  Returns from 3 daily bars: 2024-01-01 close $100.00 to 2024-01-15 close $120.00\n"));
+        assert!(output.contains("MSFT: entry $410.00 from the fixed price, not fetched\n"));
+        assert!(output.contains("NEW: entry $10.00 from the fixed price, not fetched\n  No price history; annual return is zero\n"));
+    }
+
+    #[tokio::test]
+    async fn pattern_weights_blend_the_annual_return() {
+        // UP hits the 40% cap and FLAT earns nothing
+        let gain = |pattern: &str| {
+            let source = format!("INVEST 1000\nTIME 1y\nINVESTMENT UP Up\nINVESTMENT FLAT Flat\nPATTERN p {}\nTEST p", pattern);
+            async move {
+                let results = simulate(&source, HistoryProvider::new([year_of("UP", 150), year_of("FLAT", 100)])).await;
+                results[0].percentage_gain
+            }
+        };
+        assert_eq!(gain("Up, Flat").await, Decimal::from(20));
+        assert_eq!(gain("Up:0.75, Flat:0.25").await, Decimal::from(30));
+        assert_eq!(gain("Up:3, Flat:1").await, Decimal::from(30));
+
+        let results = simulate(
+            "INVEST 1000\nTIME 1y\nINVESTMENT UP Up\nINVESTMENT FLAT Flat\nPATTERN p Up:0.75, Flat:0.25\nTEST p",
+            HistoryProvider::new([year_of("UP", 150), year_of("FLAT", 100)]),
+        ).await;
+        let values: Vec<Decimal> = results[0].ending_positions.iter().map(|p| p.value).collect();
+        assert_eq!(values, [Decimal::from(975), Decimal::from(325)]);
+    }
+
+    #[tokio::test]
+    async fn cash_buffer_caps_positions_and_earns_nothing() {
+        let full = capped_year("").await;
+        let buffered = capped_year("CASH_BUFFER 5%").await;
+
+        for snapshot in &buffered.snapshots {
+            let invested: Decimal = snapshot.holdings.iter().map(|h| h.value).sum();
+            assert_eq!(invested + snapshot.cash, snapshot.total_value);
+            assert!(invested <= snapshot.total_value * Decimal::new(95, 2), "week {}", snapshot.week);
+        }
+        // The reserve doesn't grow, so the gain stays below 95% of the fully
+        // invested 40%
+        assert_eq!(full.percentage_gain, Decimal::from(40));
+        assert!(buffered.percentage_gain > Decimal::from(37) && buffered.percentage_gain < Decimal::from(38),
+            "{}", buffered.percentage_gain);
+    }
+
+    #[tokio::test]
+    async fn order_by_sorts_results_along_the_requested_dimensions() {
+        let provider = HistoryProvider::new([year_of("UP", 120), year_of("FLAT", 100)]);
+        let mut results = simulate(
+            "INVEST 2000, 1000\nTIME 8w, 4w\nINVESTMENT UP Up\nINVESTMENT FLAT Flat\nPATTERN rising Up\nPATTERN idle Flat\n\
+             TEST rising\nTEST idle",
+            provider,
+        ).await;
+        let keys = |results: &[SimulationResult]| -> Vec<String> {
+            results.iter().map(|r| format!("{} {} {}", r.time_frame, r.initial_amount, r.pattern_name)).collect()
+        };
+        // Run order nests tests, then amounts, then frames
+        assert_eq!(keys(&results)[..3], ["8w 2000 rising", "4w 2000 rising", "8w 1000 rising"]);
+
+        Simulator::order_results(&mut results, &"frame,amount".parse().unwrap());
+        assert_eq!(keys(&results), [
+            "8w 2000 rising", "8w 2000 idle", "8w 1000 rising", "8w 1000 idle",
+            "4w 2000 rising", "4w 2000 idle", "4w 1000 rising", "4w 1000 idle",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn dividend_yield_compounds_with_price_growth() {
+        let final_amount = |extra: &str| {
+            let source = format!("INVEST 1000\nTIME 2y\nINVESTMENT FLAT Flat\nPATTERN p Flat\nTEST p\n{}", extra);
+            async move { simulate(&source, HistoryProvider::new([year_of("FLAT", 100)])).await[0].final_amount }
+        };
+        assert_eq!(final_amount("").await, Decimal::from(1000));
+        assert_eq!(final_amount("DIVIDEND FLAT 0").await, Decimal::from(1000));
+        // 3% a year, reinvested: 1000 * 1.03^2
+        assert_eq!(final_amount("DIVIDEND FLAT 0.03").await, Decimal::new(10609, 1));
+        assert_eq!(final_amount("DIVIDEND FLAT 3%").await, Decimal::new(10609, 1));
+    }
+
+    #[tokio::test]
+    async fn full_simulation_runs_against_fixed_prices() {
+        let source = "INVEST 1000, 5000\nTIME 4w, 1y\nINVESTMENT AAPL Apple\nINVESTMENT MSFT Microsoft\n\
+                      PATTERN both Apple, Microsoft\nPATTERN solo Microsoft\nTEST both\nTEST solo";
+        let provider = FixedPriceProvider::new(prices(&[("AAPL", 200), ("MSFT", 400)]));
+        let results = simulate(source, provider).await;
+
+        let runs: Vec<String> = results.iter()
+            .map(|r| format!("{} {} {}", r.pattern_name, r.initial_amount, r.time_frame))
+            .collect();
+        assert_eq!(runs, [
+            "both 1000 4w", "both 1000 1y", "both 5000 4w", "both 5000 1y",
+            "solo 1000 4w", "solo 1000 1y", "solo 5000 4w", "solo 5000 1y",
+        ]);
+        // Fixed prices carry no history, so nothing grows or shrinks
+        for result in &results {
+            assert_eq!(result.final_amount, result.initial_amount);
+            assert_eq!(result.total_gain, Decimal::ZERO);
+            let price = if result.pattern_name == "both" { 200 } else { 400 };
+            assert_eq!(result.trades[0].shares_bought, result.initial_amount / Decimal::from(price));
+        }
+
+        let dsl = StockDSL::parse(source).unwrap();
+        let mut missing = Simulator::with_provider(FixedPriceProvider::new(prices(&[("AAPL", 200)])));
+        assert!(missing.run_simulations(&dsl).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn end_align_decides_the_final_close_of_the_annual_return() {
+        // A year of Friday closes rising to 120, then a spike to 140 on a Tuesday
+        let mut mid_week = year_of("MID", 120);
+        for bar in &mut mid_week.historical_prices {
+            bar.date += chrono::Duration::days(4);
+        }
+        let mut spike = mid_week.historical_prices.last().unwrap().clone();
+        spike.date += chrono::Duration::days(4);
+        spike.close = Decimal::from(140);
+        mid_week.historical_prices.push(spike);
+
+        let final_amount = |align: &str, endpoint: &str| {
+            let source = format!(
+                "INVEST 1000\nTIME 1y\nINVESTMENT MID Mid\nPATTERN p Mid\nTEST p\nEND_ALIGN {}\nRETURN_ENDPOINT {}",
+                align, endpoint,
+            );
+            let provider = HistoryProvider::new([mid_week.clone()]);
+            async move { simulate(&source, provider).await[0].final_amount }
+        };
+
+        // Complete stops at the last Friday's 120, about 20% a year; partial
+        // takes the Tuesday spike
+        let complete = final_amount("complete", "raw").await;
+        let partial = final_amount("partial", "raw").await;
+        assert!(complete > Decimal::from(1200) && complete < Decimal::from(1202), "{}", complete);
+        assert!(partial > Decimal::from(1390), "{}", partial);
+
+        let complete = final_amount("complete", "trimmed 2").await;
+        let partial = final_amount("partial", "trimmed 2").await;
+        assert!(partial > complete, "{} vs {}", partial, complete);
+    }
+
+    #[tokio::test]
+    async fn fees_only_forgo_growth_on_the_exposed_share() {
+        let swinging: Vec<i64> = (0..=52).map(|week| 100 + 2 * week + if week % 2 == 0 { 0 } else { 30 }).collect();
+        let run = |fee: &str| {
+            let source = format!("INVEST 1000\nTIME 1y\nVOL_TARGET 10%\n{}\nINVESTMENT SWNG Swing\nPATTERN wild Swing\nTEST wild", fee);
+            let provider = HistoryProvider::new([series("SWNG", &swinging)]);
+            async move { simulate(&source, provider).await.remove(0) }
+        };
+        let free = run("").await;
+        let charged = run("FEE 100").await;
+        let exposure = charged.vol_target_exposure.unwrap();
+        assert!(exposure < Decimal::ONE, "{}", exposure);
+
+        // Only the exposed share of the fee would have compounded at the 40% cap;
+        // the rest simply never sat in cash
+        let cost = free.final_amount - charged.final_amount;
+        let expected = Decimal::from(100) + Decimal::from(40) * exposure;
+        assert_eq!(cost.round_dp(6), expected.round_dp(6));
+
+        let last = charged.snapshots.last().unwrap();
+        assert_eq!(last.cash.round_dp(6), (Decimal::from(900) * (Decimal::ONE - exposure)).round_dp(6));
+        let holdings: Decimal = last.holdings.iter().map(|holding| holding.value).sum();
+        assert_eq!((last.cash + holdings).round_dp(6), last.total_value.round_dp(6));
+    }
+
+    #[tokio::test]
+    async fn compare_fees_reruns_each_model_on_the_same_strategy() {
+        let dsl = StockDSL::parse(
+            "INVEST 1000\nTIME 1y\nFEE 10%\nFEE_MODEL discount 4.95\nFEE_MODEL advisor 1%\nINVESTMENT UP Up\nPATTERN p Up\nTEST p",
+        ).unwrap();
+        let mut simulator = Simulator::with_provider(HistoryProvider::new([year_of("UP", 150)]));
+        let comparisons = simulator.compare_fees(&dsl).await.unwrap();
+
+        // 4.95 flat leaves 995.05 to grow 40%; 1% leaves 990. FEE itself isn't a model.
+        let summary: Vec<(&str, Decimal, Decimal)> = comparisons.iter()
+            .map(|comparison| (comparison.fee_model.as_str(), comparison.total_fees, comparison.final_amount))
+            .collect();
+        assert_eq!(summary, [
+            ("discount", Decimal::new(495, 2), Decimal::new(139307, 2)),
+            ("advisor", Decimal::from(10), Decimal::from(1386)),
+        ]);
+
+        let mut out = Vec::new();
+        Simulator::write_fee_comparison(&mut out, &comparisons, &OutputOptions { quiet: true, ..OutputOptions::default() }).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Simulation      Fee model  Total fees  Net final\n\
+             p $1,000.00 1y  discount        $4.95  $1,393.07\n\
+             p $1,000.00 1y  advisor        $10.00  $1,386.00\n",
+        );
+    }
+}